@@ -0,0 +1,116 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{AbsRequest, Clock, SystemClock};
+
+/// Adjusts how many requests of a round run at once from their observed
+/// latencies and failures. Attached to runs with
+/// `RunOptions::adaptive_concurrency`.
+///
+/// Each round is dispatched in waves of at most `limit()` requests. After
+/// a wave that used the whole limit and in which every request finished
+/// within the target latency, counted from the start of the wave, the limit
+/// grows by one; after a wave in which some request was slower, or too many
+/// failed, it is halved. Share one controller between runs, for instance
+/// behind an `Arc`, to carry the limit over from one run to the next.
+#[derive(Debug)]
+pub struct AdaptiveConcurrency {
+    target: Duration,
+    min: usize,
+    max: usize,
+    max_error_rate: f64,
+    limit: AtomicUsize,
+    clock: Arc<dyn Clock>,
+}
+
+impl AdaptiveConcurrency {
+    /// A controller aiming to keep latencies under `target`, with a limit
+    /// between 1 and 256 that starts at 1.
+    pub fn new(target: Duration) -> AdaptiveConcurrency {
+        AdaptiveConcurrency {
+            target,
+            min: 1,
+            max: 256,
+            max_error_rate: 0.1,
+            limit: AtomicUsize::new(1),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Keeps the limit between `min` and `max`, starting it at `min`.
+    pub fn bounds(mut self, min: usize, max: usize) -> AdaptiveConcurrency {
+        assert!(0 < min && min <= max, "invalid concurrency bounds");
+        self.min = min;
+        self.max = max;
+        self.limit = AtomicUsize::new(min);
+        self
+    }
+
+    /// Starts the limit at `limit`, within the bounds.
+    pub fn initial_limit(self, limit: usize) -> AdaptiveConcurrency {
+        self.limit
+            .store(limit.clamp(self.min, self.max), Ordering::SeqCst);
+        self
+    }
+
+    /// Halves the limit after a wave in which more than `rate` of the
+    /// requests failed. Defaults to 0.1.
+    pub fn max_error_rate(mut self, rate: f64) -> AdaptiveConcurrency {
+        self.max_error_rate = rate;
+        self
+    }
+
+    /// Measures latencies, and the round budget, with `clock` instead of
+    /// the system clock.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> AdaptiveConcurrency {
+        self.clock = clock;
+        self
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::SeqCst)
+    }
+
+    // Runs `round` wave by wave. Once `budget` has passed, the requests of
+    // the waves that have not started are handed back instead of run.
+    pub(crate) fn run_round(
+        &self,
+        mut round: Vec<AbsRequest>,
+        budget: Option<Duration>,
+    ) -> Vec<AbsRequest> {
+        use rayon::prelude::*;
+        let elapsed_since = |start| self.clock.now().saturating_duration_since(start);
+        let start = self.clock.now();
+        while !round.is_empty() {
+            let limit = self.limit();
+            let rest = round.split_off(limit.min(round.len()));
+            let wave_start = self.clock.now();
+            let outcomes: Vec<(Duration, bool)> = round
+                .into_par_iter()
+                .map(|req| {
+                    let ok = req.run();
+                    (elapsed_since(wave_start), ok)
+                })
+                .collect();
+            self.observe(limit, &outcomes);
+            round = rest;
+            if budget.is_some_and(|budget| elapsed_since(start) >= budget) {
+                break;
+            }
+        }
+        round
+    }
+
+    fn observe(&self, limit: usize, outcomes: &[(Duration, bool)]) {
+        let slow = outcomes.iter().any(|&(latency, _)| latency > self.target);
+        let failed = outcomes.iter().filter(|&&(_, ok)| !ok).count();
+        if slow || failed as f64 > self.max_error_rate * outcomes.len() as f64 {
+            self.limit
+                .store((limit / 2).max(self.min), Ordering::SeqCst);
+        } else if outcomes.len() == limit {
+            self.limit
+                .store((limit + 1).min(self.max), Ordering::SeqCst);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{Fetch, Traversable};
+
+type Factory<T, E> = Box<dyn FnOnce() -> Fetch<T, E> + Send>;
+
+struct Job<T, E> {
+    factory: Factory<T, E>,
+    reply: Sender<Result<T, E>>,
+}
+
+/// Groups fetches submitted over time, possibly from many threads, into
+/// batches run together, so that their requests share rounds.
+///
+/// A batch is flushed once it holds `max_items` fetches, or `window` after
+/// its first fetch was picked up, whichever comes first. Batches run one at
+/// a time on a background thread; fetches submitted while a batch runs wait
+/// for the next one. Dropping the batcher flushes what is left and waits
+/// for it to finish.
+pub struct TimeBatcher<T, E> {
+    jobs: Option<Sender<Job<T, E>>>,
+    batches: Arc<AtomicUsize>,
+    worker: Option<thread::JoinHandle<()>>,
+}
+
+impl<T: Send + 'static, E: Send + 'static> TimeBatcher<T, E> {
+    pub fn new(window: Duration, max_items: usize) -> TimeBatcher<T, E> {
+        assert!(max_items > 0, "max_items must be positive");
+        let (jobs, rx) = mpsc::channel();
+        let batches = Arc::new(AtomicUsize::new(0));
+        let counter = batches.clone();
+        let worker = thread::spawn(move || {
+            while let Some(batch) = next_batch(&rx, window, max_items) {
+                run_batch(batch, &counter);
+            }
+        });
+        TimeBatcher {
+            jobs: Some(jobs),
+            batches,
+            worker: Some(worker),
+        }
+    }
+
+    /// Queues the fetch built by `factory` for the next batch. The factory
+    /// runs on the batcher's thread, since fetches cannot be sent across
+    /// threads; the fetch's outcome is sent over the returned receiver.
+    pub fn submit(
+        &self,
+        factory: impl FnOnce() -> Fetch<T, E> + Send + 'static,
+    ) -> Receiver<Result<T, E>> {
+        let (reply, rx) = mpsc::channel();
+        let job = Job {
+            factory: Box::new(factory),
+            reply,
+        };
+        // the worker only stops once the batcher is dropped
+        let _ = self.jobs.as_ref().unwrap().send(job);
+        rx
+    }
+
+    /// The number of batches run so far.
+    pub fn batches(&self) -> usize {
+        self.batches.load(Ordering::SeqCst)
+    }
+}
+
+impl<T, E> Drop for TimeBatcher<T, E> {
+    fn drop(&mut self) {
+        drop(self.jobs.take());
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+// Waits for a job, then collects the batch it starts. Returns `None` once
+// the batcher is gone and every job has been handed out.
+fn next_batch<T, E>(
+    rx: &Receiver<Job<T, E>>,
+    window: Duration,
+    max_items: usize,
+) -> Option<Vec<Job<T, E>>> {
+    let first = rx.recv().ok()?;
+    let deadline = Instant::now() + window;
+    let mut batch = vec![first];
+    while batch.len() < max_items {
+        match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(job) => batch.push(job),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    Some(batch)
+}
+
+fn run_batch<T: 'static, E: 'static>(batch: Vec<Job<T, E>>, batches: &AtomicUsize) {
+    let (factories, replies): (Vec<_>, Vec<_>) = batch
+        .into_iter()
+        .map(|job| (job.factory, job.reply))
+        .unzip();
+    let fetch = factories
+        .into_iter()
+        .traverse(|factory| factory().try_fetch());
+    let Ok(results) = fetch.run();
+    // counted before replying, so a submitter sees its own batch
+    batches.fetch_add(1, Ordering::SeqCst);
+    for (reply, res) in replies.into_iter().zip(results) {
+        // the submitter may have stopped listening
+        let _ = reply.send(res);
+    }
+}
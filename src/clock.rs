@@ -0,0 +1,68 @@
+use std::fmt;
+#[cfg(any(test, feature = "test-util"))]
+use std::sync::Mutex;
+#[cfg(any(test, feature = "test-util"))]
+use std::time::Duration;
+use std::time::Instant;
+
+/// A source of the current time for code measuring durations, such as
+/// `Fetch::timed_with` and `AdaptiveConcurrency::clock`, so that it can be
+/// tested against a clock that only moves when told to.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// The system's monotonic clock, used unless another one is given.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that stands still from the moment it is created until moved
+/// forward, for instance by the requests of a test standing in for slow
+/// ones. Available with the `test-util` feature.
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug)]
+pub struct MockClock {
+    start: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl MockClock {
+    pub fn new() -> MockClock {
+        MockClock {
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+
+    /// Moves the clock to `elapsed` after its creation, unless it is
+    /// already past that.
+    pub fn advance_to(&self, elapsed: Duration) {
+        let mut current = self.elapsed.lock().unwrap();
+        *current = (*current).max(elapsed);
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Default for MockClock {
+    fn default() -> MockClock {
+        MockClock::new()
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+}
@@ -0,0 +1,73 @@
+//! The stepping protocol every `Fetch` combinator is built on, for writing
+//! combinators outside this crate.
+//!
+//! Stepping a fetch with `Fetch::force_step` either finishes it or blocks
+//! it on a set of requests, handing back the fetch to step once they have
+//! run. A combinator is a fetch made with `Fetch::from_step_fn` that steps
+//! its operands and reassembles their steps; the requests of operands that
+//! should share a round are merged with `BlockedRequests::merge`.
+
+use crate::{vec_merge, AbsRequest, Fetch, ReqResult};
+
+/// The outcome of stepping a fetch once.
+pub enum Step<T, E> {
+    Done(T),
+    Throw(E),
+    /// The fetch needs `BlockedRequests` to run before the continuation can
+    /// make progress. The continuation must be stepped again afterwards
+    /// even when no requests are pending, since requests deferred by the
+    /// run loop only complete in a later round.
+    Blocked(BlockedRequests, Fetch<T, E>),
+}
+
+/// The requests a blocked fetch waits on. They are opaque: requests are only
+/// issued by the leaves made with `Fetch::new` and its relatives, and only
+/// dispatched by the run loop. Requests must be passed on, not dropped: a
+/// fetch waiting on a dropped request panics when stepped.
+pub struct BlockedRequests(Vec<AbsRequest>);
+
+impl BlockedRequests {
+    /// No requests, for a fetch that merely needs to wait for a round.
+    pub fn new() -> BlockedRequests {
+        BlockedRequests(Vec::new())
+    }
+
+    /// Combines two sets of requests into one dispatched in a single round.
+    pub fn merge(self, other: BlockedRequests) -> BlockedRequests {
+        BlockedRequests(vec_merge(self.0, other.0))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Default for BlockedRequests {
+    fn default() -> BlockedRequests {
+        BlockedRequests::new()
+    }
+}
+
+impl<T: 'static, E: 'static> Fetch<T, E> {
+    /// A fetch which, when stepped, returns the step computed by `f`.
+    pub fn from_step_fn(f: impl FnOnce() -> Step<T, E> + 'static) -> Fetch<T, E> {
+        Fetch(Box::new(|| match f() {
+            Step::Done(a) => ReqResult::Done(a),
+            Step::Throw(e) => ReqResult::Throw(e),
+            Step::Blocked(br, c) => ReqResult::Blocked(br.0, c),
+        }))
+    }
+
+    /// Steps this fetch once.
+    pub fn force_step(self) -> Step<T, E> {
+        match self.get()() {
+            ReqResult::Done(a) => Step::Done(a),
+            ReqResult::Throw(e) => Step::Throw(e),
+            ReqResult::Blocked(br, c) => Step::Blocked(BlockedRequests(br), c),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::cell::{Cell, RefCell};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The longest chain of requests each depending on the previous one,
+/// reported by `RunOptions::collect_critical_path`.
+///
+/// `rounds` counts the requests on the chain and `duration` sums their
+/// latencies, leaving out the time spent waiting for the rest of each
+/// round. The two may come from different chains.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CriticalPath {
+    pub rounds: usize,
+    pub duration: Duration,
+}
+
+impl CriticalPath {
+    fn max(self, other: CriticalPath) -> CriticalPath {
+        CriticalPath {
+            rounds: self.rounds.max(other.rounds),
+            duration: self.duration.max(other.duration),
+        }
+    }
+}
+
+thread_local! {
+    // The longest chain behind the results read so far by the fetch being
+    // stepped, while a run collecting the critical path is stepping on this
+    // thread.
+    static CURRENT: Cell<Option<CriticalPath>> = const { Cell::new(None) };
+    static LONGEST: RefCell<Option<Arc<Mutex<CriticalPath>>>> = const { RefCell::new(None) };
+}
+
+// Collects the critical path on the current thread until dropped.
+pub(crate) struct CriticalGuard(Option<CriticalPath>, Option<Arc<Mutex<CriticalPath>>>);
+
+impl CriticalGuard {
+    pub(crate) fn enable(longest: Arc<Mutex<CriticalPath>>) -> CriticalGuard {
+        CriticalGuard(
+            CURRENT.with(|current| current.replace(Some(CriticalPath::default()))),
+            LONGEST.with(|cell| cell.replace(Some(longest))),
+        )
+    }
+
+    // Forgets the results read by the previous step of the run.
+    pub(crate) fn next_step(&self) {
+        CURRENT.with(|current| current.set(Some(CriticalPath::default())));
+    }
+}
+
+impl Drop for CriticalGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.0));
+        LONGEST.with(|cell| *cell.borrow_mut() = self.1.take());
+    }
+}
+
+// Where a request sits in the dependency graph: it depends on the results
+// read before it was issued, and whatever reads its own result depends on it.
+pub(crate) struct LeafPath {
+    parent: CriticalPath,
+    level: Mutex<Option<CriticalPath>>,
+    longest: Arc<Mutex<CriticalPath>>,
+}
+
+impl LeafPath {
+    // The position of a request being issued now, if the run collects the
+    // critical path.
+    pub(crate) fn issue() -> Option<Arc<LeafPath>> {
+        let parent = CURRENT.with(Cell::get)?;
+        let longest = LONGEST.with(|cell| cell.borrow().clone())?;
+        Some(Arc::new(LeafPath {
+            parent,
+            level: Mutex::new(None),
+            longest,
+        }))
+    }
+
+    pub(crate) fn record(&self, latency: Duration) {
+        let level = CriticalPath {
+            rounds: self.parent.rounds + 1,
+            duration: self.parent.duration + latency,
+        };
+        *self.level.lock().unwrap() = Some(level);
+        let mut longest = self.longest.lock().unwrap();
+        *longest = longest.max(level);
+    }
+
+    // Marks the request's result as read by the fetch being stepped.
+    pub(crate) fn read(&self) {
+        if let Some(level) = *self.level.lock().unwrap() {
+            CURRENT.with(|current| current.set(current.get().map(|c| c.max(level))));
+        }
+    }
+}
+
+// Steps two independent fetches, so that requests issued by `b` do not
+// depend on the results read by `a`.
+pub(crate) fn siblings<A, B>(a: impl FnOnce() -> A, b: impl FnOnce() -> B) -> (A, B) {
+    let start = CURRENT.with(Cell::get);
+    let ra = a();
+    let after_a = CURRENT.with(|current| current.replace(start));
+    let rb = b();
+    CURRENT.with(|current| {
+        current.set(match (after_a, current.get()) {
+            (Some(x), Some(y)) => Some(x.max(y)),
+            (x, y) => x.or(y),
+        })
+    });
+    (ra, rb)
+}
@@ -0,0 +1,213 @@
+use std::any::{self, Any, TypeId};
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+
+use crate::{Fetch, FetchStatus, PendingRequest, ReqResult, Request, RequestKey};
+
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+/// An object-safe counterpart of `Request`, for requests that are only
+/// known at runtime. Its output is type-erased and recovered by
+/// `Fetch::new_dyn`.
+///
+/// Any `Request` whose error converts into a `BoxError` can be turned into
+/// a `DynRequest` with `dyn_request`.
+pub trait DynRequest: Send {
+    fn run_boxed(self: Box<Self>) -> Result<Box<dyn Any + Send>, BoxError>;
+
+    /// A hash identifying the request, playing the role `Hash` plays for
+    /// `Request`.
+    fn key(&self) -> u64;
+
+    /// A human readable description of the request.
+    fn describe(&self) -> String;
+}
+
+struct Erased<R, T, E>(R, PhantomData<fn() -> (T, E)>);
+
+impl<R, T, E> DynRequest for Erased<R, T, E>
+where
+    R: Request<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: Into<BoxError> + 'static,
+{
+    fn run_boxed(self: Box<Self>) -> Result<Box<dyn Any + Send>, BoxError> {
+        match self.0.run() {
+            Ok(res) => Ok(Box::new(res)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn key(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        TypeId::of::<R>().hash(&mut hasher);
+        self.0.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn describe(&self) -> String {
+        any::type_name::<R>().to_string()
+    }
+}
+
+/// Erases the output and error types of `request`.
+pub fn dyn_request<R, T, E>(request: R) -> Box<dyn DynRequest>
+where
+    R: Request<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: Into<BoxError> + 'static,
+{
+    Box::new(Erased(request, PhantomData))
+}
+
+/// Thrown by a `Fetch::new_dyn` fetch whose request produced a value of
+/// another type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DowncastError {
+    pub request: String,
+    pub expected: &'static str,
+}
+
+impl fmt::Display for DowncastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "request {} did not produce a {}",
+            self.request, self.expected
+        )
+    }
+}
+
+impl Error for DowncastError {}
+
+struct DynPending<T> {
+    request: Box<dyn DynRequest>,
+    status: Arc<Mutex<FetchStatus<T, BoxError>>>,
+}
+
+impl<T: Any + Send> PendingRequest for DynPending<T> {
+    fn request_mut(&mut self) -> &mut dyn Any {
+        &mut self.request
+    }
+
+    fn run(self: Box<Self>) -> bool {
+        let request = self.request.describe();
+        let res = self
+            .request
+            .run_boxed()
+            .and_then(|res| match res.downcast::<T>() {
+                Ok(res) => Ok(*res),
+                Err(_) => Err(Box::new(DowncastError {
+                    request,
+                    expected: any::type_name::<T>(),
+                }) as BoxError),
+            });
+        let ok = res.is_ok();
+        self.status.lock().unwrap().complete(res);
+        ok
+    }
+
+    fn type_name(&self) -> &'static str {
+        any::type_name::<dyn DynRequest>()
+    }
+
+    fn keys(&self) -> Option<(RequestKey, Option<RequestKey>)> {
+        Some((dyn_key(&*self.request), None))
+    }
+
+    fn shadowed(self: Box<Self>, _: &dyn Any) -> Box<dyn PendingRequest> {
+        self
+    }
+
+    fn sourced(self: Box<Self>, _: &dyn Any) -> Box<dyn PendingRequest> {
+        self
+    }
+}
+
+fn dyn_key(request: &dyn DynRequest) -> RequestKey {
+    RequestKey {
+        type_id: TypeId::of::<dyn DynRequest>(),
+        hash: request.key(),
+    }
+}
+
+impl<T: Any + Send> Fetch<T, BoxError> {
+    /// Lifts a type-erased request into a `Fetch`, throwing a
+    /// `DowncastError` if it does not produce a `T`.
+    #[track_caller]
+    pub fn new_dyn(request: Box<dyn DynRequest>) -> Fetch<T, BoxError> {
+        let key = dyn_key(&*request);
+        Fetch::blocked_on(key, None, move |status| DynPending { request, status })
+    }
+}
+
+impl<T: 'static, E: 'static> Fetch<T, E> {
+    /// Boxes the errors of this fetch, so that fetches over different
+    /// error types can be combined and the error types kept out of
+    /// signatures. The concrete error can be recovered with
+    /// `catch_downcast`. Erasing an already boxed error keeps the box as is.
+    pub fn erase_err(self) -> Fetch<T, BoxError>
+    where
+        E: Into<BoxError>,
+    {
+        self.erase_err_with(Into::into)
+    }
+
+    /// Like `erase_err`, converting errors with `f`, for error types that do
+    /// not implement `Error`.
+    pub fn erase_err_with(self, f: impl FnOnce(E) -> BoxError + 'static) -> Fetch<T, BoxError> {
+        self.catch_map(f)
+    }
+
+    /// Boxes the value of this fetch, so that fetches over different value
+    /// types can be stored side by side, for instance in a `Vec`. The
+    /// concrete value is recovered with `Fetch::downcast`.
+    pub fn erase_result(self) -> Fetch<Box<dyn Any>, E>
+    where
+        T: Any,
+    {
+        self.fmap(|a| Box::new(a) as Box<dyn Any>)
+    }
+}
+
+impl<E: 'static> Fetch<Box<dyn Any>, E> {
+    /// Recovers a value boxed by `erase_result`, handing the box back
+    /// unchanged if it does not hold a `T`.
+    pub fn downcast<T: Any>(self) -> Fetch<Result<T, Box<dyn Any>>, E> {
+        self.fmap(|a| a.downcast::<T>().map(|a| *a))
+    }
+}
+
+/// Handles errors of type `Err` thrown by `f`, in any round, with
+/// `handler`; any other error is rethrown unchanged. Chained calls behave
+/// like a `match` over the concrete error types.
+pub fn catch_downcast<Err, T, F>(f: Fetch<T, BoxError>, handler: F) -> Fetch<T, BoxError>
+where
+    Err: Error + 'static,
+    T: 'static,
+    F: FnOnce(Err) -> Fetch<T, BoxError> + 'static,
+{
+    Fetch(Box::new(|| match f.get()() {
+        ReqResult::Done(a) => ReqResult::Done(a),
+        ReqResult::Blocked(br, c) => ReqResult::Blocked(br, catch_downcast(c, handler)),
+        ReqResult::Throw(e) => match e.downcast::<Err>() {
+            Ok(e) => handler(*e).get()(),
+            Err(e) => ReqResult::Throw(e),
+        },
+    }))
+}
+
+impl<T: 'static> Fetch<T, BoxError> {
+    /// Method form of `catch_downcast`.
+    pub fn catch_downcast<Err, F>(self, handler: F) -> Fetch<T, BoxError>
+    where
+        Err: Error + 'static,
+        F: FnOnce(Err) -> Fetch<T, BoxError> + 'static,
+    {
+        catch_downcast(self, handler)
+    }
+}
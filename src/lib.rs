@@ -3,28 +3,509 @@ use std::sync::Arc;
 use std::sync::Mutex;
 use std::*;
 
+mod adaptive;
+mod batcher;
+mod clock;
+pub mod core;
+mod critical;
+mod dynamic;
+mod metrics;
 mod monad;
+mod options;
+mod shadow;
+mod trace;
+
+pub use adaptive::AdaptiveConcurrency;
+pub use batcher::TimeBatcher;
+#[cfg(any(test, feature = "test-util"))]
+pub use clock::MockClock;
+pub use clock::{Clock, SystemClock};
+pub use critical::CriticalPath;
+pub use dynamic::{catch_downcast, dyn_request, BoxError, DowncastError, DynRequest};
+pub use metrics::MetricsExporter;
+pub use options::{DetailedStats, RunError, RunOptions, RunOutcome, RunStats};
+pub use shadow::{ShadowConfig, ShadowCounters, ShadowMismatch};
 
 pub trait Request<T, E = Impossible>: Hash + Clone + Eq {
     fn run(self) -> Result<T, E>;
+
+    /// Requests of the same type returning the same key are spaced out by
+    /// `RunOptions::throttle_per_key`. Requests without a key are never
+    /// throttled.
+    fn throttle_key(&self) -> Option<u64> {
+        None
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Impossible {}
 
-struct AbsRequest(Box<dyn FnOnce() + Send>);
+impl fmt::Display for Impossible {
+    fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+impl error::Error for Impossible {}
+
+// A request waiting to be dispatched, together with the cell its
+// continuation reads the outcome from.
+trait PendingRequest: Send {
+    fn request_mut(&mut self) -> &mut dyn any::Any;
+
+    // Runs the request, returning whether it succeeded.
+    fn run(self: Box<Self>) -> bool;
+
+    // Completes the request with `source` instead of running it, if
+    // `source` is a `Source` for the request's types.
+    fn answer(&mut self, _source: &dyn any::Any) -> bool {
+        false
+    }
+
+    fn type_name(&self) -> &'static str;
+
+    // The request's key and throttle key, recomputed from the request as it
+    // is now, if they derive from it.
+    fn keys(&self) -> Option<(RequestKey, Option<RequestKey>)> {
+        None
+    }
+
+    // Wraps the request so that its outcome is also passed to the hook
+    // `shadow` returns for it, if `shadow` is a `Shadow` for its types.
+    fn shadowed(self: Box<Self>, shadow: &dyn any::Any) -> Box<dyn PendingRequest>;
+
+    // Wraps the request so that it is answered by `source` when dispatched,
+    // if `source` is a `PooledSource` for its types.
+    fn sourced(self: Box<Self>, source: &dyn any::Any) -> Box<dyn PendingRequest>;
+}
+
+// A stand-in for `Request::run`, registered with `RunOptions::override_source`.
+type Source<R, T, E> = Box<dyn Fn(&R) -> Result<T, E> + Send + Sync>;
+
+// Like `Source`, but run where the request would have run; see
+// `RunOptions::compare_sources`.
+type PooledSource<R, T, E> = Arc<Source<R, T, E>>;
+
+// Receives the outcome of a shadowed request, right after it has run.
+type Hook<T, E> = Box<dyn FnOnce(&Result<T, E>) + Send>;
+
+// Decides whether to shadow a request; see `RunOptions::shadow`.
+type Shadow<R, T, E> = Box<dyn Fn(&R) -> Option<Hook<T, E>> + Send + Sync>;
+
+// A shadowed request: it runs as before, then its hook sees the outcome.
+struct Observed<R, T, E> {
+    request: R,
+    hook: Hook<T, E>,
+    complete: Box<dyn FnOnce(Result<T, E>) + Send>,
+}
+
+impl<R, T, E> PendingRequest for Observed<R, T, E>
+where
+    R: Request<T, E> + Send + 'static,
+    T: 'static,
+    E: 'static,
+{
+    fn request_mut(&mut self) -> &mut dyn any::Any {
+        &mut self.request
+    }
+
+    fn run(self: Box<Self>) -> bool {
+        let res = self.request.run();
+        let ok = res.is_ok();
+        (self.hook)(&res);
+        (self.complete)(res);
+        ok
+    }
+
+    fn type_name(&self) -> &'static str {
+        any::type_name::<R>()
+    }
+
+    fn keys(&self) -> Option<(RequestKey, Option<RequestKey>)> {
+        Some((
+            RequestKey::of(&self.request),
+            RequestKey::throttle_of(&self.request),
+        ))
+    }
+
+    fn shadowed(self: Box<Self>, _: &dyn any::Any) -> Box<dyn PendingRequest> {
+        self
+    }
+
+    fn sourced(self: Box<Self>, _: &dyn any::Any) -> Box<dyn PendingRequest> {
+        self
+    }
+}
+
+// A request answered by a `PooledSource` instead of `Request::run`.
+struct Sourced<R, T, E> {
+    request: R,
+    source: PooledSource<R, T, E>,
+    complete: Box<dyn FnOnce(Result<T, E>) + Send>,
+}
+
+impl<R, T, E> PendingRequest for Sourced<R, T, E>
+where
+    R: Request<T, E> + Send + 'static,
+    T: 'static,
+    E: 'static,
+{
+    fn request_mut(&mut self) -> &mut dyn any::Any {
+        &mut self.request
+    }
+
+    fn run(self: Box<Self>) -> bool {
+        let res = (self.source)(&self.request);
+        let ok = res.is_ok();
+        (self.complete)(res);
+        ok
+    }
+
+    fn type_name(&self) -> &'static str {
+        any::type_name::<R>()
+    }
+
+    fn keys(&self) -> Option<(RequestKey, Option<RequestKey>)> {
+        Some((
+            RequestKey::of(&self.request),
+            RequestKey::throttle_of(&self.request),
+        ))
+    }
+
+    fn shadowed(self: Box<Self>, _: &dyn any::Any) -> Box<dyn PendingRequest> {
+        self
+    }
+
+    fn sourced(self: Box<Self>, _: &dyn any::Any) -> Box<dyn PendingRequest> {
+        self
+    }
+}
+
+struct Pending<R, T, E> {
+    request: R,
+    status: Arc<Mutex<FetchStatus<T, E>>>,
+}
+
+impl<R, T, E> PendingRequest for Pending<R, T, E>
+where
+    R: Request<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    fn request_mut(&mut self) -> &mut dyn any::Any {
+        &mut self.request
+    }
+
+    fn run(self: Box<Self>) -> bool {
+        let res = self.request.run();
+        let ok = res.is_ok();
+        self.status.lock().unwrap().complete(res);
+        ok
+    }
+
+    fn answer(&mut self, source: &dyn any::Any) -> bool {
+        match source.downcast_ref::<Source<R, T, E>>() {
+            Some(source) => {
+                self.status.lock().unwrap().complete(source(&self.request));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        any::type_name::<R>()
+    }
+
+    fn keys(&self) -> Option<(RequestKey, Option<RequestKey>)> {
+        Some((
+            RequestKey::of(&self.request),
+            RequestKey::throttle_of(&self.request),
+        ))
+    }
+
+    fn shadowed(self: Box<Self>, shadow: &dyn any::Any) -> Box<dyn PendingRequest> {
+        let hook = shadow
+            .downcast_ref::<Shadow<R, T, E>>()
+            .and_then(|shadow| shadow(&self.request));
+        match hook {
+            Some(hook) => {
+                let Pending { request, status } = *self;
+                Box::new(Observed {
+                    request,
+                    hook,
+                    complete: Box::new(move |res| status.lock().unwrap().complete(res)),
+                })
+            }
+            None => self,
+        }
+    }
+
+    fn sourced(self: Box<Self>, source: &dyn any::Any) -> Box<dyn PendingRequest> {
+        match source.downcast_ref::<PooledSource<R, T, E>>() {
+            Some(source) => {
+                let Pending { request, status } = *self;
+                Box::new(Sourced {
+                    request,
+                    source: source.clone(),
+                    complete: Box::new(move |res| status.lock().unwrap().complete(res)),
+                })
+            }
+            None => self,
+        }
+    }
+}
+
+// The key of a request lifted with `Fetch::new_with_key`, wrapped so that
+// it never equals the key of a request whose type happens to be `K`, nor
+// picks up the per-type settings registered for `K`.
+#[derive(Hash)]
+struct Keyed<K>(K);
+
+// A request lifted with `Fetch::new_with_key`.
+struct KeyedPending<R, T, E> {
+    request: R,
+    status: Arc<Mutex<FetchStatus<T, E>>>,
+}
+
+impl<R, T, E> PendingRequest for KeyedPending<R, T, E>
+where
+    R: FnOnce() -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    fn request_mut(&mut self) -> &mut dyn any::Any {
+        &mut self.request
+    }
+
+    fn run(self: Box<Self>) -> bool {
+        let res = (self.request)();
+        let ok = res.is_ok();
+        self.status.lock().unwrap().complete(res);
+        ok
+    }
+
+    fn type_name(&self) -> &'static str {
+        any::type_name::<R>()
+    }
+
+    fn shadowed(self: Box<Self>, _: &dyn any::Any) -> Box<dyn PendingRequest> {
+        self
+    }
+
+    fn sourced(self: Box<Self>, _: &dyn any::Any) -> Box<dyn PendingRequest> {
+        self
+    }
+}
+
+// Identifies a request by its type and hash, standing in for the request
+// itself wherever requests are compared.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct RequestKey {
+    type_id: any::TypeId,
+    hash: u64,
+}
+
+impl RequestKey {
+    fn of<R: Hash + 'static>(request: &R) -> RequestKey {
+        let mut hasher = collections::hash_map::DefaultHasher::new();
+        request.hash(&mut hasher);
+        RequestKey {
+            type_id: any::TypeId::of::<R>(),
+            hash: hash::Hasher::finish(&hasher),
+        }
+    }
+
+    fn throttle_of<R: Request<T, E> + 'static, T, E>(request: &R) -> Option<RequestKey> {
+        request.throttle_key().map(|hash| RequestKey {
+            type_id: any::TypeId::of::<R>(),
+            hash,
+        })
+    }
+}
+
+// Like `Pending`, but stores the request's outcome as a value.
+struct CapturedPending<R, T, E> {
+    request: R,
+    status: Arc<Mutex<FetchStatus<Result<T, E>, Impossible>>>,
+}
+
+impl<R, T, E> PendingRequest for CapturedPending<R, T, E>
+where
+    R: Request<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    fn request_mut(&mut self) -> &mut dyn any::Any {
+        &mut self.request
+    }
+
+    fn run(self: Box<Self>) -> bool {
+        let res = self.request.run();
+        let ok = res.is_ok();
+        *self.status.lock().unwrap() = FetchStatus::FetchSuccess(res);
+        ok
+    }
+
+    fn answer(&mut self, source: &dyn any::Any) -> bool {
+        match source.downcast_ref::<Source<R, T, E>>() {
+            Some(source) => {
+                *self.status.lock().unwrap() = FetchStatus::FetchSuccess(source(&self.request));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        any::type_name::<R>()
+    }
+
+    fn keys(&self) -> Option<(RequestKey, Option<RequestKey>)> {
+        Some((
+            RequestKey::of(&self.request),
+            RequestKey::throttle_of(&self.request),
+        ))
+    }
+
+    fn shadowed(self: Box<Self>, shadow: &dyn any::Any) -> Box<dyn PendingRequest> {
+        let hook = shadow
+            .downcast_ref::<Shadow<R, T, E>>()
+            .and_then(|shadow| shadow(&self.request));
+        match hook {
+            Some(hook) => {
+                let CapturedPending { request, status } = *self;
+                Box::new(Observed {
+                    request,
+                    hook,
+                    complete: Box::new(move |res| {
+                        *status.lock().unwrap() = FetchStatus::FetchSuccess(res)
+                    }),
+                })
+            }
+            None => self,
+        }
+    }
+
+    fn sourced(self: Box<Self>, source: &dyn any::Any) -> Box<dyn PendingRequest> {
+        match source.downcast_ref::<PooledSource<R, T, E>>() {
+            Some(source) => {
+                let CapturedPending { request, status } = *self;
+                Box::new(Sourced {
+                    request,
+                    source: source.clone(),
+                    complete: Box::new(move |res| {
+                        *status.lock().unwrap() = FetchStatus::FetchSuccess(res)
+                    }),
+                })
+            }
+            None => self,
+        }
+    }
+}
+
+struct AbsRequest {
+    key: RequestKey,
+    throttle: Option<RequestKey>,
+    // set by the run loop for throttled requests
+    not_before: Option<time::Instant>,
+    // set while the run collects the critical path
+    path: Option<Arc<critical::LeafPath>>,
+    // set by the run loop when the run has a `MetricsExporter`
+    exporter: Option<Arc<dyn MetricsExporter>>,
+    pending: Box<dyn PendingRequest>,
+    // where the request was lifted into a `Fetch`, reported if it panics
+    #[cfg(debug_assertions)]
+    origin: &'static panic::Location<'static>,
+}
 
 impl AbsRequest {
-    pub fn run(self) {
-        (self.0)();
+    // Recomputes the keys of a request that has been rewritten, so that it
+    // is counted and throttled as the request it has become.
+    fn rekey(&mut self) {
+        if let Some((key, throttle)) = self.pending.keys() {
+            self.key = key;
+            self.throttle = throttle;
+        }
+    }
+
+    pub fn run(mut self) -> bool {
+        if let Some(not_before) = self.not_before {
+            thread::sleep(not_before.saturating_duration_since(time::Instant::now()));
+        }
+        let (path, exporter) = (self.path.take(), self.exporter.take());
+        if path.is_none() && exporter.is_none() {
+            return self.run_pending();
+        }
+        let name = self.pending.type_name();
+        let start = time::Instant::now();
+        let ok = self.run_pending();
+        let latency = start.elapsed();
+        if let Some(path) = path {
+            path.record(latency);
+        }
+        if let Some(exporter) = exporter {
+            exporter.record_request(name, latency, ok);
+        }
+        ok
+    }
+
+    #[cfg(not(debug_assertions))]
+    fn run_pending(self) -> bool {
+        self.pending.run()
+    }
+
+    #[cfg(debug_assertions)]
+    fn run_pending(self) -> bool {
+        let origin = self.origin;
+        let pending = self.pending;
+        match panic::catch_unwind(panic::AssertUnwindSafe(|| pending.run())) {
+            Ok(ok) => ok,
+            Err(payload) => {
+                let msg = match payload.downcast_ref::<&str>() {
+                    Some(msg) => msg.to_string(),
+                    None => match payload.downcast_ref::<String>() {
+                        Some(msg) => msg.clone(),
+                        None => "Box<dyn Any>".to_string(),
+                    },
+                };
+                panic::resume_unwind(Box::new(format!(
+                    "request constructed at {} panicked: {}",
+                    origin, msg
+                )));
+            }
+        }
     }
 }
 
 impl AbsRequest {
     fn run_all(reqs: Vec<AbsRequest>) {
         use rayon::prelude::*;
-        reqs.into_par_iter().for_each(|req| req.run());
+        reqs.into_par_iter().for_each(|req| {
+            req.run();
+        });
         // reqs.into_iter().for_each(|req| req.run());
     }
+
+    // Like `run_all`, but requests that have not started once `budget` has
+    // passed are handed back instead of run. At least one request is always
+    // started so every round makes progress.
+    fn run_within(reqs: Vec<AbsRequest>, budget: time::Duration) -> Vec<AbsRequest> {
+        use rayon::prelude::*;
+        use sync::atomic::{AtomicBool, Ordering};
+        let start = time::Instant::now();
+        let started = AtomicBool::new(false);
+        let deferred = Mutex::new(Vec::new());
+        reqs.into_par_iter().for_each(|req| {
+            if start.elapsed() >= budget && started.load(Ordering::SeqCst) {
+                deferred.lock().unwrap().push(req);
+            } else {
+                started.store(true, Ordering::SeqCst);
+                req.run();
+            }
+        });
+        deferred.into_inner().unwrap()
+    }
 }
 
 #[derive(Debug)]
@@ -34,6 +515,15 @@ enum FetchStatus<T, E = Impossible> {
     FetchException(E),
 }
 
+impl<T, E> FetchStatus<T, E> {
+    fn complete(&mut self, res: Result<T, E>) {
+        match res {
+            Ok(res) => *self = FetchStatus::FetchSuccess(res),
+            Err(e) => *self = FetchStatus::FetchException(e),
+        }
+    }
+}
+
 enum ReqResult<T, E> {
     Done(T),
     Blocked(Vec<AbsRequest>, Fetch<T, E>),
@@ -42,6 +532,14 @@ enum ReqResult<T, E> {
 
 pub struct Fetch<T, E = Impossible>(Box<dyn FnOnce() -> ReqResult<T, E>>);
 
+// A fetch is an unevaluated computation, so there is nothing to show but
+// its type; this lets results holding fetches be unwrapped.
+impl<T, E> fmt::Debug for Fetch<T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Fetch { .. }")
+    }
+}
+
 impl<T: 'static, E: 'static> From<ReqResult<T, E>> for Fetch<T, E> {
     fn from(req_res: ReqResult<T, E>) -> Self {
         Fetch(Box::new(|| req_res))
@@ -58,46 +556,157 @@ impl<T: 'static> Fetch<T, Impossible> {
     }
 }
 
+impl<T: Send + 'static, E: 'static> Fetch<Vec<T>, E> {
+    /// Maps `f` over the items of this fetch in parallel on the global rayon
+    /// thread pool, once they are available, for expensive CPU-bound
+    /// post-processing. The mapping runs in the step that receives the
+    /// items and issues no requests, so it costs no round.
+    pub fn par_map<U: Send + 'static>(
+        self,
+        f: impl Fn(T) -> U + Send + Sync + 'static,
+    ) -> Fetch<Vec<U>, E> {
+        use rayon::prelude::*;
+        self.fmap(move |items| items.into_par_iter().map(f).collect())
+    }
+}
+
 impl<T: 'static, E: 'static> From<Result<T, E>> for Fetch<T, E> {
     fn from(res: Result<T, E>) -> Self {
         match res {
             Ok(res) => Fetch::pure(res),
-            Err(e) => throw(e)
+            Err(e) => throw(e),
         }
     }
 }
 
-impl<T: 'static + Send + fmt::Debug, E: Send + 'static> Fetch<T, E> {
+impl<T: 'static + Send, E: Send + 'static> Fetch<T, E> {
+    /// Lifts a request into a `Fetch`.
+    ///
+    /// The request's output is moved from the request straight into the
+    /// continuation and is never copied or buffered in between, so a request
+    /// may return a stream (e.g. `Box<dyn io::Read + Send>` or an iterator of
+    /// chunks) for the continuation to consume lazily. Such a stream lives as
+    /// long as the continuation holds on to it; it does not have to be
+    /// drained before the next round begins.
+    ///
+    /// In debug builds the caller's location is recorded and reported if the
+    /// request panics; use `new_located` to have it reported in errors too.
+    #[track_caller]
     pub fn new<R: Request<T, E> + 'static + Send>(request: R) -> Fetch<T, E> {
-        Fetch(Box::new(|| {
+        let key = RequestKey::of(&request);
+        let throttle = RequestKey::throttle_of(&request);
+        Fetch::blocked_on(key, throttle, move |status| Pending { request, status })
+    }
+
+    /// Like `new`, but an error returned by the request is wrapped in a
+    /// `Located` recording where `new_located` was called, so that a request
+    /// built in a helper reports the helper's line. Unlike the location
+    /// reported on panics, this one is recorded in release builds too.
+    #[track_caller]
+    pub fn new_located<R: Request<T, E> + 'static + Send>(request: R) -> Fetch<T, Located<E>> {
+        let origin = panic::Location::caller();
+        Fetch::new(request).catch_map(move |error| Located { origin, error })
+    }
+
+    /// Lifts a request into a `Fetch` that never throws: the request's
+    /// outcome becomes the value. Equivalent to `Fetch::new(request).try_fetch()`,
+    /// and dispatched exactly like `Fetch::new(request)`.
+    #[track_caller]
+    pub fn new_result<R: Request<T, E> + 'static + Send>(
+        request: R,
+    ) -> Fetch<Result<T, E>, Impossible> {
+        let key = RequestKey::of(&request);
+        let throttle = RequestKey::throttle_of(&request);
+        Fetch::blocked_on(key, throttle, move |status| CapturedPending {
+            request,
+            status,
+        })
+    }
+
+    /// Lifts a request given as a closure into a `Fetch`, identified by `key`
+    /// instead of by its own `Hash` and `Eq`. Fetches with equal keys stand
+    /// for the same request, which lets a request capture handles, such as
+    /// connections, that have no sensible identity.
+    ///
+    /// Such requests are never throttled and cannot be overridden or
+    /// shadowed, since they have no `Request` type to register them by.
+    #[track_caller]
+    pub fn new_with_key<R, K>(request: R, key: K) -> Fetch<T, E>
+    where
+        R: FnOnce() -> Result<T, E> + Send + 'static,
+        K: Hash + 'static,
+    {
+        let key = RequestKey::of(&Keyed(key));
+        Fetch::blocked_on(key, None, move |status| KeyedPending { request, status })
+    }
+
+    // A leaf blocked on a single request, built by `pending` around the cell
+    // the request writes its outcome to.
+    #[track_caller]
+    fn blocked_on<P: PendingRequest + 'static>(
+        key: RequestKey,
+        throttle: Option<RequestKey>,
+        pending: impl FnOnce(Arc<Mutex<FetchStatus<T, E>>>) -> P + 'static,
+    ) -> Fetch<T, E> {
+        #[cfg(debug_assertions)]
+        let origin = panic::Location::caller();
+        Fetch(Box::new(move || {
             // TODO: Arc and Mutex seems unnecessary, because
             // there will only ever be two reference, and one
             // is write, one is read. These two will never be concurrent.
             let status = Arc::new(Mutex::new(FetchStatus::<T, E>::NotFetched));
-            let modifier = status.clone();
-            let abs_request = move || {
-                let res = request.run();
-                let mut m = modifier.lock().unwrap();
-                match res {
-                    Ok(res) => *m = FetchStatus::FetchSuccess(res),
-                    Err(e) => *m = FetchStatus::FetchException(e),
-                }
-            };
+            let path = critical::LeafPath::issue();
             ReqResult::Blocked(
-                vec![AbsRequest(Box::new(abs_request))],
-                Fetch(Box::new(move || {
-                    let v: &mut FetchStatus<T, E> = &mut status.as_ref().lock().unwrap();
-                    match mem::replace(v, FetchStatus::NotFetched) {
-                        FetchStatus::FetchSuccess(v) => ReqResult::Done(v),
-                        FetchStatus::FetchException(e) => ReqResult::Throw(e),
-                        _ => unreachable!(),
-                    }
-                })),
+                vec![AbsRequest {
+                    key,
+                    throttle,
+                    not_before: None,
+                    path: path.clone(),
+                    exporter: None,
+                    pending: Box::new(pending(status.clone())),
+                    #[cfg(debug_assertions)]
+                    origin,
+                }],
+                Fetch::await_status(status, path),
             )
         }))
     }
 }
 
+impl<T: 'static, E: 'static> Fetch<T, E> {
+    // Reads the outcome of a request once it has run. A request can be
+    // deferred to a later round, in which case this stays blocked, without
+    // issuing anything new, until it has. A request that was dropped
+    // without running, say by a combinator built on `core`, would leave it
+    // blocked forever, so that panics instead.
+    fn await_status(
+        status: Arc<Mutex<FetchStatus<T, E>>>,
+        path: Option<Arc<critical::LeafPath>>,
+    ) -> Fetch<T, E> {
+        Fetch(Box::new(move || {
+            let v: &mut FetchStatus<T, E> = &mut status.as_ref().lock().unwrap();
+            let res = match mem::replace(v, FetchStatus::NotFetched) {
+                FetchStatus::FetchSuccess(v) => ReqResult::Done(v),
+                FetchStatus::FetchException(e) => ReqResult::Throw(e),
+                // the request holds the only other reference to `status`
+                FetchStatus::NotFetched if Arc::strong_count(&status) == 1 => {
+                    panic!("a request was dropped before it ran, so its result will never arrive")
+                }
+                FetchStatus::NotFetched => {
+                    return ReqResult::Blocked(
+                        Vec::new(),
+                        Fetch::await_status(status.clone(), path),
+                    );
+                }
+            };
+            if let Some(path) = path {
+                path.read();
+            }
+            res
+        }))
+    }
+}
+
 pub fn throw<T: 'static, E: 'static>(e: E) -> Fetch<T, E> {
     Fetch(Box::new(|| ReqResult::Throw(e)))
 }
@@ -153,75 +762,636 @@ impl<T: 'static, E: 'static> Fetch<T, E> {
         }))
     }
 
-    pub fn run(self) -> Result<T, E> {
-        match self.get()() {
-            ReqResult::Done(a) => Ok(a),
-            ReqResult::Blocked(br, c) => {
-                AbsRequest::run_all(br);
-                c.run()
-            }
-            ReqResult::Throw(e) => Err(e),
-        }
+    /// Passes a reference to the value of this fetch to `sink`, for
+    /// instance an audit log, and yields the value unchanged, like `tee` in
+    /// a shell pipeline. `sink` is not called if the fetch throws. Since it
+    /// only borrows the value, `T` need not be `Clone`.
+    pub fn tee(self, sink: impl FnOnce(&T) + 'static) -> Fetch<T, E> {
+        self.fmap(|a| {
+            sink(&a);
+            a
+        })
     }
-}
 
-pub fn ap<T, U, F, E>(f: Fetch<F, E>, x: Fetch<T, E>) -> Fetch<U, E>
-where
-    T: 'static,
-    E: 'static,
-    U: 'static,
-    F: FnOnce(T) -> U + 'static,
-{
-    Fetch(Box::new(|| match (f.get()(), x.get()()) {
-        (ReqResult::Done(f), ReqResult::Done(x)) => ReqResult::Done(f(x)),
-        (ReqResult::Done(f), ReqResult::Blocked(br, c)) => ReqResult::Blocked(br, c.fmap(f)),
-        (ReqResult::Blocked(br, c), ReqResult::Done(x)) => {
-            ReqResult::Blocked(br, ap(c, Fetch::pure(x)))
-        }
-        (ReqResult::Blocked(br1, f), ReqResult::Blocked(br2, x)) => {
-            ReqResult::Blocked(vec_merge(br1, br2), ap(f, x))
-        }
-        (ReqResult::Done(_g), ReqResult::Throw(e)) => ReqResult::Throw(e),
-        (ReqResult::Throw(e), _) => ReqResult::Throw(e),
-        (ReqResult::Blocked(br, c), ReqResult::Throw(e)) => ReqResult::Blocked(br, ap(c, throw(e))),
-    }))
-}
+    /// Like `tee`, but sends a clone of the value over `tx`, which is why
+    /// `T` must be `Clone`. A receiver that has hung up is ignored.
+    pub fn tee_clone(self, tx: sync::mpsc::Sender<T>) -> Fetch<T, E>
+    where
+        T: Clone,
+    {
+        self.tee(move |a| {
+            let _ = tx.send(a.clone());
+        })
+    }
 
-#[allow(unused_macros)]
-macro_rules! lift_builder {
-    ($func:ident; $F:ident; $U:ident; $($T:ident),+; $f:ident; $($x:ident),+) => {
-        pub fn $func<
-            E:'static,
-            $($T:'static),+,
-            U:'static,
-            F:FnOnce($($T),+) -> $U + 'static
-        >($f: $F, $($x:crate::Fetch<$T,E>),+) -> crate::Fetch<$U,E> {
-            let $f = crate::Fetch::pure($f);
-            lift_builder!(@fmap $f, $($x),+);
-            lift_builder!(@ap $f; $($x),+)
-        }
-    };
-    (@fmap $f:ident, $($x:ident),+) => {
-        let $f = $f.fmap(|$f| lift_builder!(@fmap_lambda $($x),+; $f, $($x),+));
-    };
-    (@fmap_lambda $x:ident; $f:ident, $($args:ident),+) => {
-        |$x| $f($($args),+)
-    };
-    (@fmap_lambda $x:ident, $($xs:ident),*; $($args:ident),+) => {
-        |$x| lift_builder!(@fmap_lambda $($xs),*; $($args),+)
-    };
-    (@ap $f:expr; $x:ident, $($xs:ident),*) => {
-        lift_builder!(@ap crate::ap($f, $x); $($xs),*)
-    };
-    (@ap $f:expr; $x:ident) => {
-        crate::ap($f, $x)
-    };
-}
+    /// Converts the value of this fetch with `U::from`.
+    pub fn map_into<U: From<T> + 'static>(self) -> Fetch<U, E> {
+        self.fmap(U::from)
+    }
 
-lift_builder!(lift2; F; U; T1, T2; f; x1, x2);
-lift_builder!(lift3; F; U; T1, T2, T3; f; x1, x2, x3);
-lift_builder!(lift4; F; U; T1, T2, T3, T4; f; x1, x2, x3, x4);
-lift_builder!(lift5; F; U; T1, T2, T3, T4, T5; f; x1, x2, x3, x4, x5);
+    /// Converts the error of this fetch with `E2::from`, the way `?` does,
+    /// for instance to embed it into the error enum of a larger fetch.
+    pub fn map_err_into<E2: From<E> + 'static>(self) -> Fetch<T, E2> {
+        self.catch_map(E2::from)
+    }
+
+    /// Like `fmap`, but an `Err` returned by `f` is thrown once the value
+    /// becomes available.
+    pub fn try_fmap<U: 'static>(self, f: impl FnOnce(T) -> Result<U, E> + 'static) -> Fetch<U, E> {
+        Fetch(Box::new(|| match self.get()() {
+            ReqResult::Done(a) => match f(a) {
+                Ok(b) => ReqResult::Done(b),
+                Err(e) => ReqResult::Throw(e),
+            },
+            ReqResult::Blocked(br, c) => ReqResult::Blocked(br, c.try_fmap(f)),
+            ReqResult::Throw(e) => ReqResult::Throw(e),
+        }))
+    }
+
+    /// Checks the value of this fetch with `check`, throwing the error it
+    /// returns and otherwise passing the value through.
+    pub fn validate(self, check: impl FnOnce(&T) -> Result<(), E> + 'static) -> Fetch<T, E> {
+        self.try_fmap(|a| check(&a).map(|()| a))
+    }
+
+    /// Continues with the fetch returned by `f` if this fetch succeeds,
+    /// and yields `default` if it throws, discarding the error. Errors
+    /// thrown by the continuation still propagate, so the error type stays
+    /// `E`.
+    pub fn then_if_ok<U: 'static>(
+        self,
+        f: impl FnOnce(T) -> Fetch<U, E> + 'static,
+        default: U,
+    ) -> Fetch<U, E> {
+        self.try_fetch().into().bind(|res| match res {
+            Ok(a) => f(a),
+            Err(_) => Fetch::pure(default),
+        })
+    }
+
+    /// Like `bind`, but an `Err` returned by `k` is thrown instead of
+    /// continuing.
+    pub fn try_bind<U: 'static>(
+        self,
+        k: impl FnOnce(T) -> Result<Fetch<U, E>, E> + 'static,
+    ) -> Fetch<U, E> {
+        self.bind(|a| match k(a) {
+            Ok(next) => next,
+            Err(e) => throw(e),
+        })
+    }
+
+    /// Recovers from an error thrown by this fetch with the fetch returned by
+    /// `handler`, typically one that tries again (for instance by re-issuing
+    /// the original request). Errors thrown by the retry propagate.
+    pub fn catch_with_retry(self, handler: impl FnOnce(E) -> Fetch<T, E> + 'static) -> Fetch<T, E> {
+        Fetch(Box::new(|| match self.get()() {
+            ReqResult::Done(a) => ReqResult::Done(a),
+            ReqResult::Blocked(br, c) => ReqResult::Blocked(br, c.catch_with_retry(handler)),
+            ReqResult::Throw(e) => handler(e).get()(),
+        }))
+    }
+
+    /// Passes an error thrown by this fetch, in any round, to `f` before
+    /// propagating it unchanged. Meant for logging; use `catch` to recover.
+    ///
+    /// ```
+    /// use ruxl::{throw, Fetch};
+    ///
+    /// let lookup: Fetch<u32, String> = throw("no such user".to_string());
+    /// let lookup = lookup.log_err(|e| log::error!("user lookup failed: {}", e));
+    /// assert_eq!(lookup.run(), Err("no such user".to_string()));
+    /// ```
+    pub fn log_err(self, f: impl Fn(&E) + 'static) -> Fetch<T, E> {
+        Fetch(Box::new(|| match self.get()() {
+            ReqResult::Done(a) => ReqResult::Done(a),
+            ReqResult::Blocked(br, c) => ReqResult::Blocked(br, c.log_err(f)),
+            ReqResult::Throw(e) => {
+                f(&e);
+                ReqResult::Throw(e)
+            }
+        }))
+    }
+
+    /// Increments `counter` once this fetch completes, whether it succeeds
+    /// or throws, so that another thread can poll how many items of a long
+    /// traversal are done. Wrap the fetch of each item, for instance with
+    /// `traverse(move |x| f(x).accumulate_progress(counter.clone()))`; this
+    /// works the same with `traverse_to_channel` and the other variants.
+    ///
+    /// The fetch itself stays on the thread driving the run, only the
+    /// counter is shared. It is incremented as the run steps the fetch
+    /// after each round, so items completing in the same round are counted
+    /// together, before any chunk holding them is sent.
+    pub fn accumulate_progress(self, counter: Arc<sync::atomic::AtomicUsize>) -> Fetch<T, E> {
+        Fetch(Box::new(|| match self.get()() {
+            ReqResult::Blocked(br, c) => ReqResult::Blocked(br, c.accumulate_progress(counter)),
+            done_or_throw => {
+                counter.fetch_add(1, sync::atomic::Ordering::SeqCst);
+                done_or_throw
+            }
+        }))
+    }
+
+    /// Hedges this fetch: if it is still blocked `after` it was first
+    /// stepped, the fetch returned by `fallback` is started alongside it,
+    /// sharing its rounds, and whichever finishes first wins. The primary
+    /// is never cancelled and may still win.
+    ///
+    /// Rounds run to completion, so elapsed time is only checked between
+    /// rounds: the fallback joins the first round that starts after
+    /// `after`. Errors thrown by the fallback are ignored in favour of the
+    /// primary; errors thrown by the primary propagate.
+    pub fn hedge(
+        self,
+        after: time::Duration,
+        fallback: impl FnOnce() -> Fetch<T, E> + 'static,
+    ) -> Fetch<T, E> {
+        Fetch(Box::new(move || {
+            self.hedge_since(time::Instant::now(), after, fallback)
+                .get()()
+        }))
+    }
+
+    fn hedge_since(
+        self,
+        start: time::Instant,
+        after: time::Duration,
+        fallback: impl FnOnce() -> Fetch<T, E> + 'static,
+    ) -> Fetch<T, E> {
+        Fetch(Box::new(move || match self.get()() {
+            ReqResult::Blocked(br, c) if start.elapsed() >= after => match fallback().get()() {
+                ReqResult::Done(b) => ReqResult::Done(b),
+                ReqResult::Blocked(br2, f) => {
+                    ReqResult::Blocked(vec_merge(br, br2), c.race_fallback(f))
+                }
+                ReqResult::Throw(_) => ReqResult::Blocked(br, c),
+            },
+            ReqResult::Blocked(br, c) => {
+                ReqResult::Blocked(br, c.hedge_since(start, after, fallback))
+            }
+            done_or_throw => done_or_throw,
+        }))
+    }
+
+    fn race_fallback(self, fallback: Fetch<T, E>) -> Fetch<T, E> {
+        Fetch(Box::new(|| match self.get()() {
+            ReqResult::Blocked(br, c) => match fallback.get()() {
+                ReqResult::Done(b) => ReqResult::Done(b),
+                ReqResult::Blocked(br2, f) => {
+                    ReqResult::Blocked(vec_merge(br, br2), c.race_fallback(f))
+                }
+                ReqResult::Throw(_) => ReqResult::Blocked(br, c),
+            },
+            done_or_throw => done_or_throw,
+        }))
+    }
+
+    /// Absorbs an error thrown in any round into `default`.
+    pub fn unwrap_or(self, default: T) -> Fetch<T, Impossible> {
+        self.unwrap_or_else(|_| default)
+    }
+
+    /// Absorbs an error thrown in any round into a value computed by `f`.
+    pub fn unwrap_or_else(self, f: impl FnOnce(E) -> T + 'static) -> Fetch<T, Impossible> {
+        Fetch(Box::new(|| match self.get()() {
+            ReqResult::Done(a) => ReqResult::Done(a),
+            ReqResult::Blocked(br, c) => ReqResult::Blocked(br, c.unwrap_or_else(f)),
+            ReqResult::Throw(e) => ReqResult::Done(f(e)),
+        }))
+    }
+
+    /// Absorbs an error thrown in any round into `T::default()`.
+    pub fn unwrap_or_default(self) -> Fetch<T, Impossible>
+    where
+        T: Default,
+    {
+        self.unwrap_or_else(|_| T::default())
+    }
+
+    /// Absorbs an error thrown in any round into `default`, pushing the
+    /// error onto `log` so that it can be reported once the run is over.
+    ///
+    /// The fetch itself stays on the thread driving the run, so `E` need
+    /// not be `Send`; it only has to be for `log` to be read from other
+    /// threads.
+    pub fn recover_log(self, default: T, log: Arc<Mutex<Vec<E>>>) -> Fetch<T, Impossible> {
+        self.unwrap_or_else(move |e| {
+            log.lock().unwrap().push(e);
+            default
+        })
+    }
+
+    /// Pairs the value of this fetch with the wall time from when it was
+    /// first stepped until it completed, and the number of rounds it was
+    /// blocked on. Nested and sibling `timed` fetches each report their own
+    /// sub-computation.
+    pub fn timed(self) -> Fetch<(T, SubTiming), E> {
+        self.timed_with(Arc::new(SystemClock))
+    }
+
+    /// Like `timed`, but reads the time from `clock`.
+    pub fn timed_with(self, clock: Arc<dyn Clock>) -> Fetch<(T, SubTiming), E> {
+        Fetch(Box::new(move || {
+            let start = clock.now();
+            self.timed_from(clock, start, 0).get()()
+        }))
+    }
+
+    fn timed_from(
+        self,
+        clock: Arc<dyn Clock>,
+        start: time::Instant,
+        rounds: usize,
+    ) -> Fetch<(T, SubTiming), E> {
+        Fetch(Box::new(move || match self.get()() {
+            ReqResult::Done(a) => ReqResult::Done((
+                a,
+                SubTiming {
+                    elapsed: clock.now().saturating_duration_since(start),
+                    rounds,
+                },
+            )),
+            ReqResult::Blocked(br, c) => {
+                ReqResult::Blocked(br, c.timed_from(clock, start, rounds + 1))
+            }
+            ReqResult::Throw(e) => ReqResult::Throw(e),
+        }))
+    }
+
+    /// Wraps an error thrown by this fetch in a `Contextual` whose context is
+    /// `msg`, describing what the fetch was doing. Further context is added
+    /// with `add_context`.
+    pub fn context(self, msg: &'static str) -> Fetch<T, Contextual<E>> {
+        self.catch_map(move |error| Contextual {
+            context: vec![msg],
+            error,
+        })
+    }
+
+    /// Throws `BudgetOr::Exceeded` instead of dispatching a round that
+    /// would bring the number of requests issued by this fetch above `max`,
+    /// for instance to bound a subquery that fans out without limiting the
+    /// rest of the run. Requests are counted as they are issued, over all
+    /// rounds, duplicates included; the round that exceeds the budget is
+    /// dropped without being dispatched.
+    pub fn budget(self, max: usize) -> Fetch<T, BudgetOr<E>> {
+        self.budget_from(max, 0)
+    }
+
+    fn budget_from(self, max: usize, issued: usize) -> Fetch<T, BudgetOr<E>> {
+        Fetch(Box::new(move || match self.get()() {
+            ReqResult::Done(a) => ReqResult::Done(a),
+            ReqResult::Blocked(br, c) => {
+                let issued = issued + br.len();
+                if issued > max {
+                    ReqResult::Throw(BudgetOr::Exceeded { max })
+                } else {
+                    ReqResult::Blocked(br, c.budget_from(max, issued))
+                }
+            }
+            ReqResult::Throw(e) => ReqResult::Throw(BudgetOr::Inner(e)),
+        }))
+    }
+
+    fn catch_map<E2: 'static>(self, f: impl FnOnce(E) -> E2 + 'static) -> Fetch<T, E2> {
+        Fetch(Box::new(|| match self.get()() {
+            ReqResult::Done(a) => ReqResult::Done(a),
+            ReqResult::Blocked(br, c) => ReqResult::Blocked(br, c.catch_map(f)),
+            ReqResult::Throw(e) => ReqResult::Throw(f(e)),
+        }))
+    }
+
+    /// Moves the outcome of this fetch into its value, so the resulting
+    /// fetch never throws.
+    pub fn try_fetch(self) -> Fetch<Result<T, E>, Impossible> {
+        Fetch(Box::new(|| match self.get()() {
+            ReqResult::Done(a) => ReqResult::Done(Ok(a)),
+            ReqResult::Blocked(br, c) => ReqResult::Blocked(br, c.try_fetch()),
+            ReqResult::Throw(e) => ReqResult::Done(Err(e)),
+        }))
+    }
+
+    /// Applies `f` to every request of type `R` issued by this fetch, in
+    /// every round, before it is dispatched.
+    ///
+    /// Since `bind`, `ap` and `traverse` surface the requests of their
+    /// sub-fetches in their own blocked rounds, `f` reaches every descendant
+    /// leaf, including those created by continuations in later rounds.
+    /// Rewritten requests are counted by `RunOptions::max_distinct_requests`
+    /// and throttled as the requests they have become.
+    pub fn interpose<R: 'static>(self, f: impl Fn(&mut R) + 'static) -> Fetch<T, E> {
+        Fetch(Box::new(|| match self.get()() {
+            ReqResult::Done(a) => ReqResult::Done(a),
+            ReqResult::Blocked(mut br, c) => {
+                for req in &mut br {
+                    if let Some(request) = req.pending.request_mut().downcast_mut::<R>() {
+                        f(request);
+                        req.rekey();
+                    }
+                }
+                ReqResult::Blocked(br, c.interpose(f))
+            }
+            ReqResult::Throw(e) => ReqResult::Throw(e),
+        }))
+    }
+
+    pub fn run(self) -> Result<T, E> {
+        match self.run_with(RunOptions::new()).result {
+            Ok(a) => Ok(a),
+            Err(RunError::Thrown(e)) => Err(e),
+            Err(_) => unreachable!("default options never abort a run"),
+        }
+    }
+
+    /// Runs this fetch, calling `f` after each round with the fraction of
+    /// `plan_estimate` requests dispatched so far, capped at 1.0.
+    ///
+    /// `plan_estimate` is typically the `RunStats::requests` of an earlier
+    /// run of the same fetch. The fraction is only exact when the shape of
+    /// the fetch does not depend on the data it fetches; otherwise it is an
+    /// estimate, and may stop short of 1.0 or reach it early.
+    pub fn run_with_progress(self, plan_estimate: usize, mut f: impl FnMut(f32)) -> Result<T, E> {
+        let mut dispatched = 0;
+        let mut step = self.get()();
+        loop {
+            match step {
+                ReqResult::Done(a) => return Ok(a),
+                ReqResult::Throw(e) => return Err(e),
+                ReqResult::Blocked(br, c) => {
+                    dispatched += br.len();
+                    AbsRequest::run_all(br);
+                    f(if dispatched >= plan_estimate {
+                        1.0
+                    } else {
+                        dispatched as f32 / plan_estimate as f32
+                    });
+                    step = c.get()();
+                }
+            }
+        }
+    }
+}
+
+/// Applies the function computed by `f` to the value computed by `x`,
+/// dispatching the requests of both in the same rounds.
+///
+/// Every request writes its outcome into a cell owned by the fetch that
+/// issued it, so which request of a round finishes first never affects the
+/// result: `ap`, the lift functions, `traverse` and `sequence` all assemble
+/// their results positionally and are deterministic for deterministic
+/// requests.
+pub fn ap<T, U, F, E>(f: Fetch<F, E>, x: Fetch<T, E>) -> Fetch<U, E>
+where
+    T: 'static,
+    E: 'static,
+    U: 'static,
+    F: FnOnce(T) -> U + 'static,
+{
+    Fetch(Box::new(|| match critical::siblings(f.get(), x.get()) {
+        (ReqResult::Done(f), ReqResult::Done(x)) => ReqResult::Done(f(x)),
+        (ReqResult::Done(f), ReqResult::Blocked(br, c)) => ReqResult::Blocked(br, c.fmap(f)),
+        (ReqResult::Blocked(br, c), ReqResult::Done(x)) => {
+            ReqResult::Blocked(br, ap(c, Fetch::pure(x)))
+        }
+        (ReqResult::Blocked(br1, f), ReqResult::Blocked(br2, x)) => {
+            ReqResult::Blocked(vec_merge(br1, br2), ap(f, x))
+        }
+        (ReqResult::Done(_g), ReqResult::Throw(e)) => ReqResult::Throw(e),
+        (ReqResult::Throw(e), _) => ReqResult::Throw(e),
+        (ReqResult::Blocked(br, c), ReqResult::Throw(e)) => ReqResult::Blocked(br, ap(c, throw(e))),
+    }))
+}
+
+/// Runs `fetches` that are meant to be independent single-round fetches,
+/// together in one round, and returns their outcomes in order.
+///
+/// Debug builds assert that the run took at most one round, catching
+/// fetches that were accidentally composed sequentially.
+pub fn run_independent<T: 'static, E: 'static>(fetches: Vec<Fetch<T, E>>) -> Vec<Result<T, E>> {
+    let outcome = fetches
+        .into_iter()
+        .traverse(Fetch::try_fetch)
+        .run_with(RunOptions::new().collect_stats(true));
+    let rounds = outcome.stats.map_or(0, |stats| stats.rounds);
+    debug_assert!(
+        rounds <= 1,
+        "independent fetches took {} rounds instead of one",
+        rounds
+    );
+    match outcome.result {
+        Ok(results) => results,
+        Err(RunError::Thrown(e)) => match e {},
+        Err(_) => unreachable!("default options never abort a run"),
+    }
+}
+
+/// Composes two fetch-producing functions into one that feeds the value
+/// fetched by `f` to `g`, Kleisli style (`f >=> g`). The results of `g`
+/// are fetched in the rounds after those of `f`.
+pub fn compose<A, B, C, E>(
+    f: impl Fn(A) -> Fetch<B, E> + 'static,
+    g: impl Fn(B) -> Fetch<C, E> + 'static,
+) -> impl Fn(A) -> Fetch<C, E>
+where
+    B: 'static,
+    C: 'static,
+    E: 'static,
+{
+    let g = rc::Rc::new(g);
+    move |a| {
+        let g = g.clone();
+        f(a).bind(move |b| g(b))
+    }
+}
+
+/// Crawls the graph whose edges are fetched by `children`, breadth first
+/// from `roots`, and returns every node reached in the order it was first
+/// reached, roots included.
+///
+/// The children of a level are fetched together, one level per round, down
+/// to `max_depth` levels below the roots; hitting the limit simply stops
+/// the crawl. Nodes already reached are not expanded again, so cycles end
+/// the crawl early too.
+pub fn expand<T, E>(
+    roots: Vec<T>,
+    max_depth: usize,
+    children: impl Fn(&T) -> Fetch<Vec<T>, E> + 'static,
+) -> Fetch<Vec<T>, E>
+where
+    T: Hash + Eq + Clone + 'static,
+    E: 'static,
+{
+    let mut seen = collections::HashSet::new();
+    let roots: Vec<T> = roots
+        .into_iter()
+        .filter(|node| seen.insert(node.clone()))
+        .collect();
+    expand_level(roots.clone(), seen, roots, max_depth, rc::Rc::new(children))
+}
+
+fn expand_level<T, E, F>(
+    mut reached: Vec<T>,
+    mut seen: collections::HashSet<T>,
+    frontier: Vec<T>,
+    depth_left: usize,
+    children: rc::Rc<F>,
+) -> Fetch<Vec<T>, E>
+where
+    T: Hash + Eq + Clone + 'static,
+    E: 'static,
+    F: Fn(&T) -> Fetch<Vec<T>, E> + 'static,
+{
+    if frontier.is_empty() || depth_left == 0 {
+        return Fetch::pure(reached);
+    }
+    let level: Vec<_> = frontier.iter().map(|node| children(node)).collect();
+    level.into_iter().sequence().bind(move |levels| {
+        let frontier: Vec<T> = levels
+            .into_iter()
+            .flatten()
+            .filter(|node| seen.insert(node.clone()))
+            .collect();
+        reached.extend(frontier.iter().cloned());
+        expand_level(reached, seen, frontier, depth_left - 1, children)
+    })
+}
+
+/// Runs all `fetches` side by side and yields the value of the first to
+/// succeed, ignoring those that throw along the way, as when trying every
+/// replica of a source. Throws the errors of all the fetches, in input
+/// order, only once every one of them has thrown; an empty `fetches`
+/// throws right away.
+///
+/// The fetches still blocked share each round. When several succeed in
+/// the same step, the first in input order wins, and the others are
+/// dropped without their pending requests being dispatched.
+pub fn race_ok<T: 'static, E: 'static>(fetches: Vec<Fetch<T, E>>) -> Fetch<T, Vec<E>> {
+    race_ok_from(fetches.into_iter().enumerate().collect(), Vec::new())
+}
+
+fn race_ok_from<T: 'static, E: 'static>(
+    fetches: Vec<(usize, Fetch<T, E>)>,
+    mut errors: Vec<(usize, E)>,
+) -> Fetch<T, Vec<E>> {
+    Fetch(Box::new(move || {
+        let mut requests = Vec::new();
+        let mut blocked = Vec::new();
+        for (i, fetch) in fetches {
+            match fetch.get()() {
+                ReqResult::Done(a) => return ReqResult::Done(a),
+                ReqResult::Blocked(br, c) => {
+                    requests = vec_merge(requests, br);
+                    blocked.push((i, c));
+                }
+                ReqResult::Throw(e) => errors.push((i, e)),
+            }
+        }
+        if blocked.is_empty() {
+            errors.sort_by_key(|&(i, _)| i);
+            ReqResult::Throw(errors.into_iter().map(|(_, e)| e).collect())
+        } else {
+            ReqResult::Blocked(requests, race_ok_from(blocked, errors))
+        }
+    }))
+}
+
+/// Runs all `fetches` side by side and folds over their outcomes in order,
+/// without stopping at the first error.
+pub fn fold_results<T, A, E>(
+    fetches: Vec<Fetch<T, E>>,
+    init: A,
+    f: impl FnMut(A, Result<T, E>) -> A + 'static,
+) -> Fetch<A, Impossible>
+where
+    T: 'static,
+    A: 'static,
+    E: 'static,
+{
+    fetches
+        .into_iter()
+        .traverse(Fetch::try_fetch)
+        .fmap(|results| results.into_iter().fold(init, f))
+}
+
+#[allow(unused_macros)]
+macro_rules! lift_builder {
+    ($func:ident; $F:ident; $U:ident; $($T:ident),+; $f:ident; $($x:ident),+) => {
+        pub fn $func<
+            E:'static,
+            $($T:'static),+,
+            U:'static,
+            F:FnOnce($($T),+) -> $U + 'static
+        >($f: $F, $($x:crate::Fetch<$T,E>),+) -> crate::Fetch<$U,E> {
+            let $f = crate::Fetch::pure($f);
+            lift_builder!(@fmap $f, $($x),+);
+            lift_builder!(@ap $f; $($x),+)
+        }
+    };
+    (@fmap $f:ident, $($x:ident),+) => {
+        let $f = $f.fmap(|$f| lift_builder!(@fmap_lambda $($x),+; $f, $($x),+));
+    };
+    (@fmap_lambda $x:ident; $f:ident, $($args:ident),+) => {
+        |$x| $f($($args),+)
+    };
+    (@fmap_lambda $x:ident, $($xs:ident),*; $($args:ident),+) => {
+        |$x| lift_builder!(@fmap_lambda $($xs),*; $($args),+)
+    };
+    (@ap $f:expr; $x:ident, $($xs:ident),*) => {
+        lift_builder!(@ap crate::ap($f, $x); $($xs),*)
+    };
+    (@ap $f:expr; $x:ident) => {
+        crate::ap($f, $x)
+    };
+}
+
+lift_builder!(lift2; F; U; T1, T2; f; x1, x2);
+lift_builder!(lift3; F; U; T1, T2, T3; f; x1, x2, x3);
+lift_builder!(lift4; F; U; T1, T2, T3, T4; f; x1, x2, x3, x4);
+lift_builder!(lift5; F; U; T1, T2, T3, T4, T5; f; x1, x2, x3, x4, x5);
+
+/// Like `lift2`, but each argument is paired with a closure recovering from
+/// its error, so a failing branch is replaced by a value of its own instead
+/// of failing the combination. The branches may have different error types,
+/// and their requests are still dispatched in the same rounds.
+pub fn lift2_with_recovery<T1, T2, U, E1, E2>(
+    f: impl FnOnce(T1, T2) -> U + 'static,
+    (x1, r1): (Fetch<T1, E1>, impl FnOnce(E1) -> T1 + 'static),
+    (x2, r2): (Fetch<T2, E2>, impl FnOnce(E2) -> T2 + 'static),
+) -> Fetch<U, Impossible>
+where
+    T1: 'static,
+    T2: 'static,
+    U: 'static,
+    E1: 'static,
+    E2: 'static,
+{
+    lift2(f, x1.unwrap_or_else(r1), x2.unwrap_or_else(r2))
+}
+
+/// The three-argument version of `lift2_with_recovery`.
+pub fn lift3_with_recovery<T1, T2, T3, U, E1, E2, E3>(
+    f: impl FnOnce(T1, T2, T3) -> U + 'static,
+    (x1, r1): (Fetch<T1, E1>, impl FnOnce(E1) -> T1 + 'static),
+    (x2, r2): (Fetch<T2, E2>, impl FnOnce(E2) -> T2 + 'static),
+    (x3, r3): (Fetch<T3, E3>, impl FnOnce(E3) -> T3 + 'static),
+) -> Fetch<U, Impossible>
+where
+    T1: 'static,
+    T2: 'static,
+    T3: 'static,
+    U: 'static,
+    E1: 'static,
+    E2: 'static,
+    E3: 'static,
+{
+    lift3(
+        f,
+        x1.unwrap_or_else(r1),
+        x2.unwrap_or_else(r2),
+        x3.unwrap_or_else(r3),
+    )
+}
 
 fn cons_f<T: 'static, E: 'static>(ys: Fetch<Vec<T>, E>, x: Fetch<T, E>) -> Fetch<Vec<T>, E> {
     lift2(
@@ -234,45 +1404,664 @@ fn cons_f<T: 'static, E: 'static>(ys: Fetch<Vec<T>, E>, x: Fetch<T, E>) -> Fetch
     )
 }
 
-pub trait Traversable<T> {
-    fn traverse<T2: 'static, E: 'static>(
-        self,
-        f: impl Fn(T) -> Fetch<T2, E> + 'static,
-    ) -> Fetch<Vec<T2>, E>;
+/// An error returned by a request, together with where the request was
+/// lifted into a `Fetch`, built by `Fetch::new_located`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Located<E> {
+    pub origin: &'static panic::Location<'static>,
+    pub error: E,
 }
 
-impl<T, I: Iterator<Item = T>> Traversable<T> for I {
-    fn traverse<T2: 'static, E: 'static>(
-        self,
-        f: impl Fn(T) -> Fetch<T2, E> + 'static,
-    ) -> Fetch<Vec<T2>, E> {
-        let init = Fetch::pure(Vec::new());
-        self.fold(init, |ys, x| cons_f(ys, f(x)))
+impl<E: fmt::Display> fmt::Display for Located<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (request constructed at {})", self.error, self.origin)
     }
 }
 
-pub trait Sequence<T, E> {
-    fn sequence(self) -> Fetch<Vec<T>, E>;
+impl<E: error::Error + 'static> error::Error for Located<E> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
 }
 
-impl<T: 'static, E: 'static, V: Iterator<Item = Fetch<T, E>> + 'static> Sequence<T, E> for V {
-    fn sequence(self) -> Fetch<Vec<T>, E> {
-        // let init = Fetch::pure(Vec::new());
-        // self.fold(init, cons_f)
-        self.traverse(|x| x)
+/// An error together with the trail of what the fetches it went through
+/// were doing, outermost first, built by `Fetch::context`.
+///
+/// Errors pass through `bind`, `ap` and the other combinators unchanged, so
+/// only the fetches on the way from the failing request to the one being
+/// run add to the trail. When siblings combined with `ap` both throw, the
+/// error reported, with its trail, is the one `ap` reports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Contextual<E> {
+    pub context: Vec<&'static str>,
+    pub error: E,
+}
+
+impl<E: fmt::Display> fmt::Display for Contextual<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for msg in &self.context {
+            write!(f, "{}: ", msg)?;
+        }
+        self.error.fmt(f)
     }
 }
 
-fn vec_merge<T>(mut a: Vec<T>, mut b: Vec<T>) -> Vec<T> {
-    if a.len() < b.len() {
-        mem::swap(&mut a, &mut b);
+impl<E: error::Error + 'static> error::Error for Contextual<E> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
     }
-    a.append(&mut b);
-    a
 }
 
-pub fn while_m<T: 'static>(mut f: impl FnMut() -> Fetch<bool, T> + 'static) -> Fetch<(), T> {
-    fetch! {
+impl<T: 'static, E: 'static> Fetch<T, Contextual<E>> {
+    /// Prepends `msg` to the context of an error thrown by this fetch.
+    pub fn add_context(self, msg: &'static str) -> Fetch<T, Contextual<E>> {
+        self.catch_map(move |mut e| {
+            e.context.insert(0, msg);
+            e
+        })
+    }
+}
+
+/// Thrown by a fetch wrapped with `Fetch::budget`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BudgetOr<E> {
+    /// The fetch would have issued more than `max` requests.
+    Exceeded { max: usize },
+    /// The fetch threw on its own.
+    Inner(E),
+}
+
+impl<E: fmt::Display> fmt::Display for BudgetOr<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BudgetOr::Exceeded { max } => write!(f, "issued more than {} requests", max),
+            BudgetOr::Inner(e) => e.fmt(f),
+        }
+    }
+}
+
+impl<E: error::Error + 'static> error::Error for BudgetOr<E> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            BudgetOr::Exceeded { .. } => None,
+            BudgetOr::Inner(e) => Some(e),
+        }
+    }
+}
+
+/// Cost of a sub-computation, reported by `Fetch::timed`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubTiming {
+    pub elapsed: time::Duration,
+    pub rounds: usize,
+}
+
+/// The completed results of a partial traversal, together with a `Fetch`
+/// over the items that were not started.
+pub type Partial<T, E> = (Vec<T>, Fetch<Vec<T>, E>);
+
+/// Returned by `result_or_partial` when a chunk throws.
+#[derive(Debug)]
+pub struct PartialError<T, E> {
+    pub error: E,
+    /// The results of the chunks that completed before the failing one.
+    pub done: Vec<T>,
+    /// A `Fetch` over the items of the failing chunk and those after it.
+    pub rest: Fetch<Vec<T>, E>,
+}
+
+/// Thrown by `traverse_within_memory` when the results collected so far
+/// exceed its budget.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResultTooLarge {
+    pub max_bytes: usize,
+    /// The estimated size of the results once the budget was exceeded.
+    pub size: usize,
+}
+
+impl fmt::Display for ResultTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "results take an estimated {} bytes, over the budget of {}",
+            self.size, self.max_bytes
+        )
+    }
+}
+
+impl error::Error for ResultTooLarge {}
+
+pub trait Traversable<T> {
+    /// Maps each item to a fetch and collects their results in input order.
+    /// All the fetches share rounds.
+    fn traverse<T2: 'static, E: 'static>(
+        self,
+        f: impl Fn(T) -> Fetch<T2, E> + 'static,
+    ) -> Fetch<Vec<T2>, E>;
+
+    /// Like `traverse`, but collects the results into a vector with room for
+    /// `len` items up front, avoiding reallocations when the number of items
+    /// is known. `len` is only a hint.
+    fn traverse_sized<T2: 'static, E: 'static>(
+        self,
+        len: usize,
+        f: impl Fn(T) -> Fetch<T2, E> + 'static,
+    ) -> Fetch<Vec<T2>, E>;
+
+    /// Traverses the items `chunk_size` at a time, running each chunk to
+    /// completion until `deadline` passes. Returns the results of the chunks
+    /// that completed together with a `Fetch` over the un-started tail.
+    ///
+    /// The deadline is only checked between chunks, so a chunk that has
+    /// started always finishes. If a chunk throws, the traversal stops
+    /// there, and the error comes with the results of the chunks before it
+    /// and a `Fetch` over the rest of the items, starting over with those
+    /// of the failing chunk, which is why they must be `Clone`.
+    fn result_or_partial<T2, E, F>(
+        self,
+        chunk_size: usize,
+        deadline: time::Instant,
+        f: F,
+    ) -> Result<Partial<T2, E>, PartialError<T2, E>>
+    where
+        T: Clone + 'static,
+        T2: 'static,
+        E: 'static,
+        F: Fn(T) -> Fetch<T2, E> + Clone + 'static;
+
+    /// Like `traverse`, but an item whose fetch throws does not fail the
+    /// whole traversal: its fetch is re-issued on its own, and only the error
+    /// of that second attempt is kept in its slot.
+    ///
+    /// Retries are batched per failing round: the retries of the items that
+    /// throw in the same round share the next one, alongside the items still
+    /// in progress. When item fetches span several rounds and fail in
+    /// different ones, each batch of retries costs rounds of its own, so the
+    /// traversal may take more than one round longer than `traverse` would.
+    /// It costs nothing extra when every item succeeds.
+    fn traverse_isolate_failures<T2, E, F>(self, f: F) -> Fetch<Vec<Result<T2, E>>, Impossible>
+    where
+        T: Clone + 'static,
+        T2: 'static,
+        E: 'static,
+        F: Fn(T) -> Fetch<T2, E> + 'static;
+
+    /// Traverses the items `chunk_size` at a time, sending each chunk's
+    /// results over `tx` in input order before the next chunk is started.
+    /// Yields the number of results sent.
+    ///
+    /// Sending happens on the thread driving the run, between rounds, so a
+    /// full channel pauses the whole run: no further chunk is dispatched,
+    /// while the requests of the current chunk have all finished. The
+    /// results held back are at most those of one chunk. If the receiver
+    /// hangs up, the remaining items are dropped without being fetched.
+    fn traverse_to_channel<T2, E, F>(
+        self,
+        chunk_size: usize,
+        tx: sync::mpsc::SyncSender<T2>,
+        f: F,
+    ) -> Fetch<usize, E>
+    where
+        Self: Sized + 'static,
+        T: 'static,
+        T2: 'static,
+        E: 'static,
+        F: Fn(T) -> Fetch<T2, E> + 'static;
+
+    /// Like `traverse`, but throws a `ResultTooLarge` as soon as the total
+    /// size of the results collected so far, as estimated by `size_of`,
+    /// exceeds `max_bytes`. Results are counted in input order as they
+    /// become available; those past the budget are dropped.
+    fn traverse_within_memory<T2, E, S, F>(
+        self,
+        max_bytes: usize,
+        size_of: S,
+        f: F,
+    ) -> Fetch<Vec<T2>, E>
+    where
+        T2: 'static,
+        E: From<ResultTooLarge> + 'static,
+        S: Fn(&T2) -> usize + 'static,
+        F: Fn(T) -> Fetch<T2, E> + 'static;
+
+    /// Like `traverse`, but sorts the results by `key`, keeping items with
+    /// equal keys in input order. The fetches still share rounds; sorting
+    /// only happens once all of them have completed.
+    fn traverse_sorted_by<T2, E, K, F>(
+        self,
+        f: F,
+        key: impl Fn(&T2) -> K + 'static,
+    ) -> Fetch<Vec<T2>, E>
+    where
+        T2: 'static,
+        E: 'static,
+        K: Ord,
+        F: Fn(T) -> Fetch<T2, E> + 'static;
+}
+
+// Sends the results of `items` over `tx` chunk by chunk, for
+// `traverse_to_channel`.
+fn send_chunks<I, T, T2, E, F>(
+    mut items: I,
+    chunk_size: usize,
+    tx: sync::mpsc::SyncSender<T2>,
+    f: rc::Rc<F>,
+    sent: usize,
+) -> Fetch<usize, E>
+where
+    I: Iterator<Item = T> + 'static,
+    T: 'static,
+    T2: 'static,
+    E: 'static,
+    F: Fn(T) -> Fetch<T2, E> + 'static,
+{
+    Fetch(Box::new(move || {
+        let chunk: Vec<T> = items.by_ref().take(chunk_size).collect();
+        if chunk.is_empty() {
+            return ReqResult::Done(sent);
+        }
+        let g = f.clone();
+        let next = chunk
+            .into_iter()
+            .traverse(move |x| g(x))
+            .bind(move |results| {
+                let mut sent = sent;
+                for res in results {
+                    if tx.send(res).is_err() {
+                        return Fetch::pure(sent);
+                    }
+                    sent += 1;
+                }
+                send_chunks(items, chunk_size, tx, f, sent)
+            });
+        next.get()()
+    }))
+}
+
+impl<T, I: Iterator<Item = T>> Traversable<T> for I {
+    fn traverse<T2: 'static, E: 'static>(
+        self,
+        f: impl Fn(T) -> Fetch<T2, E> + 'static,
+    ) -> Fetch<Vec<T2>, E> {
+        let init = Fetch::pure(Vec::new());
+        self.fold(init, |ys, x| cons_f(ys, f(x)))
+    }
+
+    fn traverse_sized<T2: 'static, E: 'static>(
+        self,
+        len: usize,
+        f: impl Fn(T) -> Fetch<T2, E> + 'static,
+    ) -> Fetch<Vec<T2>, E> {
+        let init = Fetch::pure(Vec::with_capacity(len));
+        self.fold(init, |ys, x| cons_f(ys, f(x)))
+    }
+
+    fn result_or_partial<T2, E, F>(
+        mut self,
+        chunk_size: usize,
+        deadline: time::Instant,
+        f: F,
+    ) -> Result<Partial<T2, E>, PartialError<T2, E>>
+    where
+        T: Clone + 'static,
+        T2: 'static,
+        E: 'static,
+        F: Fn(T) -> Fetch<T2, E> + Clone + 'static,
+    {
+        assert!(chunk_size > 0, "chunk_size must be positive");
+        let mut done = Vec::new();
+        while time::Instant::now() < deadline {
+            let chunk: Vec<T> = self.by_ref().take(chunk_size).collect();
+            if chunk.is_empty() {
+                break;
+            }
+            match chunk.clone().into_iter().traverse(f.clone()).run() {
+                Ok(results) => done.extend(results),
+                Err(error) => {
+                    let rest: Vec<T> = chunk.into_iter().chain(self).collect();
+                    return Err(PartialError {
+                        error,
+                        done,
+                        rest: rest.into_iter().traverse(f),
+                    });
+                }
+            }
+        }
+        let rest: Vec<T> = self.collect();
+        Ok((done, rest.into_iter().traverse(f)))
+    }
+
+    fn traverse_isolate_failures<T2, E, F>(self, f: F) -> Fetch<Vec<Result<T2, E>>, Impossible>
+    where
+        T: Clone + 'static,
+        T2: 'static,
+        E: 'static,
+        F: Fn(T) -> Fetch<T2, E> + 'static,
+    {
+        let f = rc::Rc::new(f);
+        self.traverse(move |x| {
+            let retry = f.clone();
+            f(x.clone()).catch_with_retry(move |_| retry(x)).try_fetch()
+        })
+    }
+
+    fn traverse_to_channel<T2, E, F>(
+        self,
+        chunk_size: usize,
+        tx: sync::mpsc::SyncSender<T2>,
+        f: F,
+    ) -> Fetch<usize, E>
+    where
+        Self: Sized + 'static,
+        T: 'static,
+        T2: 'static,
+        E: 'static,
+        F: Fn(T) -> Fetch<T2, E> + 'static,
+    {
+        assert!(chunk_size > 0, "chunk_size must be positive");
+        send_chunks(self, chunk_size, tx, rc::Rc::new(f), 0)
+    }
+
+    fn traverse_sorted_by<T2, E, K, F>(
+        self,
+        f: F,
+        key: impl Fn(&T2) -> K + 'static,
+    ) -> Fetch<Vec<T2>, E>
+    where
+        T2: 'static,
+        E: 'static,
+        K: Ord,
+        F: Fn(T) -> Fetch<T2, E> + 'static,
+    {
+        self.traverse(f).fmap(move |mut results| {
+            results.sort_by_key(|result| key(result));
+            results
+        })
+    }
+
+    fn traverse_within_memory<T2, E, S, F>(
+        self,
+        max_bytes: usize,
+        size_of: S,
+        f: F,
+    ) -> Fetch<Vec<T2>, E>
+    where
+        T2: 'static,
+        E: From<ResultTooLarge> + 'static,
+        S: Fn(&T2) -> usize + 'static,
+        F: Fn(T) -> Fetch<T2, E> + 'static,
+    {
+        let size_of = rc::Rc::new(size_of);
+        let init = Fetch::pure((Vec::new(), 0));
+        self.fold(init, |acc, x| {
+            let size_of = size_of.clone();
+            lift2(|acc, y| (acc, y), acc, f(x)).try_fmap(move |((mut ys, size), y)| {
+                let size = size + size_of(&y);
+                if size > max_bytes {
+                    return Err(ResultTooLarge { max_bytes, size }.into());
+                }
+                ys.push(y);
+                Ok((ys, size))
+            })
+        })
+        .fmap(|(ys, _)| ys)
+    }
+}
+
+pub trait Sequence<T, E> {
+    /// Collects the results of the fetches in input order, sharing rounds.
+    fn sequence(self) -> Fetch<Vec<T>, E>;
+}
+
+impl<T: 'static, E: 'static, V: Iterator<Item = Fetch<T, E>> + 'static> Sequence<T, E> for V {
+    fn sequence(self) -> Fetch<Vec<T>, E> {
+        // let init = Fetch::pure(Vec::new());
+        // self.fold(init, cons_f)
+        self.traverse(|x| x)
+    }
+}
+
+/// A pair from `zip_longest`, with `None` past the end of the shorter side.
+pub type Padded<A, B> = (Option<A>, Option<B>);
+
+/// Zips the results of two vector fetches, padding the shorter one with
+/// `None`. Both fetches share rounds.
+pub fn zip_longest<A, B, E>(a: Fetch<Vec<A>, E>, b: Fetch<Vec<B>, E>) -> Fetch<Vec<Padded<A, B>>, E>
+where
+    A: 'static,
+    B: 'static,
+    E: 'static,
+{
+    lift2(
+        |a: Vec<A>, b: Vec<B>| {
+            let len = a.len().max(b.len());
+            let (mut a, mut b) = (a.into_iter(), b.into_iter());
+            (0..len).map(|_| (a.next(), b.next())).collect()
+        },
+        a,
+        b,
+    )
+}
+
+/// Thrown by `group_traverse` when a group's fetch returns a different
+/// number of results than it was given items.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LengthMismatch {
+    pub expected: usize,
+    pub actual: usize,
+}
+
+impl fmt::Display for LengthMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected {} results for the group, got {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl error::Error for LengthMismatch {}
+
+/// Runs `f` over `items` on a background thread, yielding each item's
+/// outcome as soon as its fetch is done, while the others are still being
+/// fetched.
+///
+/// Results come in completion order. Unlike `run`, the items do not wait
+/// for whole rounds: each request signals the background thread as soon as
+/// it has run, and an item is stepped again once the requests it is blocked
+/// on are all done. So an item is yielded while slower requests issued in
+/// the same round are still running, and its next requests are dispatched
+/// straight away. A throwing item only yields its error and does not affect
+/// the others. If running the fetches panics, the panic is resumed on the
+/// thread iterating once the results before it have been yielded.
+///
+/// Dropping the iterator stops the background thread: requests already
+/// running finish, but no further ones are dispatched.
+pub fn run_streaming<T, T2, E, F>(
+    items: impl IntoIterator<Item = T>,
+    f: F,
+) -> impl Iterator<Item = Result<T2, E>>
+where
+    T: Send + 'static,
+    T2: Send + 'static,
+    E: Send + 'static,
+    F: Fn(T) -> Fetch<T2, E> + Send + 'static,
+{
+    let items: Vec<T> = items.into_iter().collect();
+    let (tx, rx) = sync::mpsc::channel();
+    let stopped = Arc::new(sync::atomic::AtomicBool::new(false));
+    let stop = stopped.clone();
+    let handle = thread::spawn(move || {
+        let fetches = items.into_iter().map(|x| f(x).try_fetch()).collect();
+        stream_results(fetches, &tx, &stop);
+    });
+    Streaming {
+        rx,
+        handle: Some(handle),
+        stopped,
+    }
+}
+
+// The iterator returned by `run_streaming`.
+struct Streaming<T> {
+    rx: sync::mpsc::Receiver<T>,
+    handle: Option<thread::JoinHandle<()>>,
+    // tells the background thread that nobody is listening any more
+    stopped: Arc<sync::atomic::AtomicBool>,
+}
+
+impl<T> Iterator for Streaming<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        match self.rx.recv() {
+            Ok(res) => Some(res),
+            Err(_) => {
+                if let Some(Err(payload)) = self.handle.take().map(thread::JoinHandle::join) {
+                    panic::resume_unwind(payload);
+                }
+                None
+            }
+        }
+    }
+}
+
+impl<T> Drop for Streaming<T> {
+    fn drop(&mut self) {
+        self.stopped.store(true, sync::atomic::Ordering::SeqCst);
+    }
+}
+
+// Drives `fetches` for `run_streaming`, stepping each one as soon as the
+// requests it is blocked on have run, and sends their values over `tx`.
+// Returns without dispatching anything more once `stopped` is set.
+fn stream_results<T: 'static>(
+    fetches: Vec<Fetch<T>>,
+    tx: &sync::mpsc::Sender<T>,
+    stopped: &sync::atomic::AtomicBool,
+) {
+    let (done_tx, done_rx) = sync::mpsc::channel();
+    // the continuation of each blocked fetch, with its requests still running
+    let mut waiting: Vec<Option<(usize, Fetch<T>)>> = fetches.iter().map(|_| None).collect();
+    let mut ready: Vec<(usize, Fetch<T>)> = fetches.into_iter().enumerate().collect();
+    let mut in_flight = 0;
+    loop {
+        if stopped.load(sync::atomic::Ordering::SeqCst) {
+            return;
+        }
+        for (i, fetch) in mem::take(&mut ready) {
+            match fetch.get()() {
+                ReqResult::Done(a) => {
+                    // the iterator may have been dropped
+                    let _ = tx.send(a);
+                }
+                ReqResult::Throw(e) => match e {},
+                // nothing to wait for, so the continuation can go on
+                ReqResult::Blocked(br, c) if br.is_empty() => ready.push((i, c)),
+                ReqResult::Blocked(br, c) => {
+                    waiting[i] = Some((br.len(), c));
+                    in_flight += br.len();
+                    for req in br {
+                        let done_tx = done_tx.clone();
+                        rayon::spawn(move || {
+                            let outcome = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                                req.run();
+                            }));
+                            let _ = done_tx.send((i, outcome));
+                        });
+                    }
+                }
+            }
+        }
+        if ready.is_empty() {
+            if in_flight == 0 {
+                return;
+            }
+            let (i, outcome) = done_rx
+                .recv()
+                .expect("a request was dropped without running");
+            in_flight -= 1;
+            if let Err(payload) = outcome {
+                panic::resume_unwind(payload);
+            }
+            if let Some((pending, c)) = waiting[i].take() {
+                if pending == 1 {
+                    ready.push((i, c));
+                } else {
+                    waiting[i] = Some((pending - 1, c));
+                }
+            }
+        }
+    }
+}
+
+/// Partitions `items` by `key_fn` and issues one `batch_fetch` per group
+/// with all of that group's items, in the order they appear. The groups
+/// share rounds, and their results are put back in the order of `items`.
+///
+/// `batch_fetch` must return one result per item, positionally; otherwise
+/// the traversal throws a `LengthMismatch`.
+pub fn group_traverse<T, K, U, E>(
+    items: impl IntoIterator<Item = T>,
+    key_fn: impl Fn(&T) -> K,
+    batch_fetch: impl Fn(K, Vec<T>) -> Fetch<Vec<U>, E>,
+) -> Fetch<Vec<U>, E>
+where
+    T: 'static,
+    K: Hash + Eq,
+    U: 'static,
+    E: From<LengthMismatch> + 'static,
+{
+    let mut groups = collections::HashMap::new();
+    let mut len = 0;
+    for item in items {
+        let (positions, group) = groups
+            .entry(key_fn(&item))
+            .or_insert_with(|| (Vec::new(), Vec::new()));
+        positions.push(len);
+        group.push(item);
+        len += 1;
+    }
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    groups.sort_by_key(|(_, (positions, _))| positions[0]);
+    let batches: Vec<_> = groups
+        .into_iter()
+        .map(|(key, (positions, group))| {
+            let expected = group.len();
+            batch_fetch(key, group).try_fmap(move |results| {
+                if results.len() == expected {
+                    Ok((positions, results))
+                } else {
+                    Err(E::from(LengthMismatch {
+                        expected,
+                        actual: results.len(),
+                    }))
+                }
+            })
+        })
+        .collect();
+    batches.into_iter().sequence().fmap(move |batches| {
+        let mut slots: Vec<Option<U>> = iter::repeat_with(|| None).take(len).collect();
+        for (positions, results) in batches {
+            for (pos, result) in positions.into_iter().zip(results) {
+                slots[pos] = Some(result);
+            }
+        }
+        slots.into_iter().map(Option::unwrap).collect()
+    })
+}
+
+fn vec_merge<T>(mut a: Vec<T>, mut b: Vec<T>) -> Vec<T> {
+    if a.len() < b.len() {
+        mem::swap(&mut a, &mut b);
+    }
+    a.append(&mut b);
+    a
+}
+
+pub fn while_m<T: 'static>(mut f: impl FnMut() -> Fetch<bool, T> + 'static) -> Fetch<(), T> {
+    fetch! {
         let fetch_b = f();
         b <- fetch_b;
         if b {
@@ -281,228 +2070,2180 @@ pub fn while_m<T: 'static>(mut f: impl FnMut() -> Fetch<bool, T> + 'static) -> F
             Fetch::pure(())
         }
     }
-}
+}
+
+#[cfg(test)]
+mod tests {
+    #[derive(Hash, PartialEq, Eq, Clone, Debug)]
+    pub enum Exception {
+        Msg(String),
+    }
+
+    use std::time::Duration;
+
+    use super::*;
+    #[derive(Clone, Copy, Debug)]
+    struct PostId(usize);
+    #[allow(dead_code)]
+    #[derive(Debug, Clone)]
+    struct Date(String);
+    #[derive(Debug, Clone)]
+    struct PostContent(String);
+
+    impl From<String> for PostContent {
+        fn from(content: String) -> PostContent {
+            PostContent(content)
+        }
+    }
+    #[allow(dead_code)]
+    #[derive(Debug, Clone)]
+    struct PostInfo {
+        id: PostId,
+        date: Date,
+        topic: String,
+    }
+
+    #[derive(Clone)]
+    struct SleepRequest<T> {
+        name: &'static str,
+        sleep_duration: u64,
+        result: T,
+    }
+
+    // we use name for eq and hash here, only for demonstrating purpose
+    // a real request will not have result stored in it.
+    // Then we will be able to derive Hash and Eq directly
+
+    impl<T> Hash for SleepRequest<T> {
+        fn hash<H: hash::Hasher>(&self, state: &mut H) {
+            self.name.hash(state)
+        }
+    }
+
+    impl<T> PartialEq for SleepRequest<T> {
+        fn eq(&self, other: &Self) -> bool {
+            self.name.eq(other.name)
+        }
+    }
+
+    impl<T> Eq for SleepRequest<T> {}
+
+    impl<T: Clone> Request<T> for SleepRequest<T> {
+        fn run(self) -> Result<T, Impossible> {
+            thread::sleep(Duration::from_millis(self.sleep_duration));
+            Ok(self.result)
+        }
+    }
+
+    fn get_post_ids() -> Fetch<Vec<PostId>> {
+        Fetch::new(SleepRequest {
+            name: "get_post_ids",
+            sleep_duration: 500,
+            result: vec![PostId(1), PostId(2)],
+        })
+    }
+
+    fn get_post_info(id: PostId) -> Fetch<PostInfo> {
+        Fetch::new(SleepRequest {
+            name: "get_post_info",
+            sleep_duration: 500,
+            result: PostInfo {
+                id,
+                date: Date("today".to_string()),
+                topic: ["Hello", "world"][id.0 % 2].to_string(),
+            },
+        })
+    }
+
+    fn get_post_content(id: PostId) -> Fetch<PostContent, Impossible> {
+        Fetch::new(SleepRequest {
+            name: "get_post_content",
+            sleep_duration: 500,
+            result: PostContent(format!("A post with id {}", id.0)),
+        })
+    }
+
+    fn render_posts(it: impl Iterator<Item = (PostInfo, PostContent)>) -> String {
+        it.map(|(info, content)| format!("<p>{} {}</p>", info.topic, content.0))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn render_side_pane(posts: String, topics: String) -> String {
+        format!(
+            "<div class=\"topics\">{}</div>\n<div class=\"posts\">{}</div>",
+            topics, posts
+        )
+    }
+
+    fn render_page(side: String, main: String) -> String {
+        format!(
+            "<html><body><div>{}</div><div>{}</div></body></html>",
+            side, main
+        )
+    }
+
+    fn popular_posts() -> Fetch<String> {
+        Fetch::new(SleepRequest {
+            name: "popular_posts",
+            sleep_duration: 500,
+            result: "<p>popular post 1, popular post 2, ...</p>\n".to_string(),
+        })
+    }
+
+    fn topics() -> Fetch<String> {
+        Fetch::new(SleepRequest {
+            name: "topics",
+            sleep_duration: 500,
+            result: "<p>topic 1, topic 2, ...</p>\n".to_string(),
+        })
+    }
+
+    fn left_pane() -> Fetch<String> {
+        lift2(render_side_pane, popular_posts(), topics())
+    }
+
+    fn get_all_post_info() -> Fetch<Vec<PostInfo>> {
+        fetch! {
+            ids <- get_post_ids();
+            ids.into_iter().traverse(get_post_info)
+        }
+    }
+
+    #[derive(Hash, Clone, PartialEq, Eq)]
+    struct RandomCrashRequest<T, E> {
+        err: E,
+        result: T,
+    }
+
+    impl<T, E> Request<T, E> for RandomCrashRequest<T, E>
+    where
+        T: Hash + Eq + Clone,
+        E: Hash + Eq + Clone,
+    {
+        fn run(self) -> Result<T, E> {
+            if !rand::random::<bool>() {
+                Err(self.err)
+            } else {
+                Ok(self.result)
+            }
+        }
+    }
+
+    fn random_crash_page() -> Fetch<String, Exception> {
+        Fetch::new(RandomCrashRequest {
+            err: Exception::Msg("Intended error :P".to_string()),
+            result: "".to_string(),
+        })
+    }
+
+    fn main_pane() -> Fetch<String> {
+        // get_all_post_info().bind(|posts| {
+        //     let posts2 = posts.clone();
+        //     posts
+        //         .into_iter()
+        //         .map(|post| post.id)
+        //         .map(get_post_content)
+        //         .sequence()
+        //         .bind(|content| {
+        //             let rendered = render_posts(posts2.into_iter().zip(content.into_iter()));
+        //             Fetch::<String>::pure(rendered)
+        //         })
+        // })
+        fetch! {
+            posts <- get_all_post_info();
+            let posts2 = posts.clone();
+            content <- posts.into_iter().traverse(|post| get_post_content(post.id));
+            let rendered = render_posts(posts2.into_iter().zip(content.into_iter()));
+            return rendered
+        }
+    }
+
+    fn error_page(e: Exception) -> Fetch<String> {
+        match e {
+            // Exception::HttpError(err_code) => Fetch::pure(format!("<h1> HttpError: {}", err_code)),
+            Exception::Msg(msg) => Fetch::pure(format!(
+                "An error occured ... but you received this message: {}",
+                msg
+            )),
+            // Exception::OutOfMemory => Fetch::pure("Ooof! Out Of Memory!".into()),
+            // Exception::Timeout => Fetch::pure("TvT Timeout!".into()),
+        }
+    }
+
+    fn blog() -> Fetch<String> {
+        lift2(render_page, left_pane(), main_pane())
+    }
+
+    fn blog_with_crash() -> Fetch<String, Exception> {
+        lift3(
+            |x, y, z| format!("{}<br>{}", z, render_page(y, x)),
+            left_pane().into(),
+            main_pane().into(),
+            random_crash_page(),
+        )
+    }
+
+    #[test]
+    fn run_blog() {
+        let start_time = time::Instant::now();
+        let blog = blog();
+        let blog = blog;
+        println!("{}", start_time.elapsed().as_millis());
+
+        let _result = blog.run();
+        println!("{}", start_time.elapsed().as_millis());
+    }
+    #[test]
+    fn test_random_crash() {
+        let blog_with_crash = catch(blog_with_crash(), error_page);
+        match blog_with_crash.run() {
+            Ok(result) => println!("{}", result),
+            Err(e) => match e {},
+        }
+    }
+
+    struct SyntheticStream {
+        remaining: usize,
+        reads: Arc<sync::atomic::AtomicUsize>,
+    }
+
+    impl io::Read for SyntheticStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.remaining);
+            buf[..n].iter_mut().for_each(|b| *b = 0xab);
+            self.remaining -= n;
+            self.reads.fetch_add(1, sync::atomic::Ordering::SeqCst);
+            Ok(n)
+        }
+    }
+
+    #[derive(Clone)]
+    struct BlobRequest {
+        len: usize,
+        reads: Arc<sync::atomic::AtomicUsize>,
+    }
+
+    impl Hash for BlobRequest {
+        fn hash<H: hash::Hasher>(&self, state: &mut H) {
+            self.len.hash(state)
+        }
+    }
+
+    impl PartialEq for BlobRequest {
+        fn eq(&self, other: &Self) -> bool {
+            self.len == other.len
+        }
+    }
+
+    impl Eq for BlobRequest {}
+
+    impl Request<Box<dyn io::Read + Send>> for BlobRequest {
+        fn run(self) -> Result<Box<dyn io::Read + Send>, Impossible> {
+            Ok(Box::new(SyntheticStream {
+                remaining: self.len,
+                reads: self.reads,
+            }))
+        }
+    }
+
+    #[test]
+    fn test_streaming_request() {
+        const LEN: usize = 64 << 20;
+        const CHUNK: usize = 64 << 10;
+        let reads = Arc::new(sync::atomic::AtomicUsize::new(0));
+        let blob = Fetch::new(BlobRequest {
+            len: LEN,
+            reads: reads.clone(),
+        });
+        let checksum = blob.fmap(|mut stream| {
+            let mut buf = vec![0; CHUNK];
+            let (mut total, mut matching) = (0, 0);
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break (total, matching);
+                }
+                total += n;
+                matching += buf[..n].iter().filter(|b| **b == 0xab).count();
+            }
+        });
+        let (total, matching) = checksum.run().unwrap_or_else(|e| match e {});
+        assert_eq!(total, LEN);
+        assert_eq!(matching, LEN);
+        // the consumer only ever saw `CHUNK` bytes at a time
+        assert_eq!(reads.load(sync::atomic::Ordering::SeqCst), LEN / CHUNK + 1);
+    }
+
+    // a helper building a request on behalf of its callers
+    fn located_flaky(id: usize) -> (Fetch<usize, Located<Exception>>, u32) {
+        (Fetch::new_located(FlakyRequest::new(id, 1)), line!())
+    }
+
+    #[test]
+    fn test_error_reports_origin() {
+        let (fetch, line) = located_flaky(1);
+        let err = echo(1).into().bind(move |_| fetch).run().unwrap_err();
+        assert_eq!(
+            err.error,
+            Exception::Msg("attempt 0 of 1 failed".to_string())
+        );
+        assert_eq!((err.origin.file(), err.origin.line()), (file!(), line));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_panic_reports_origin() {
+        #[derive(Hash, Clone, PartialEq, Eq)]
+        struct PanicRequest;
+
+        impl Request<()> for PanicRequest {
+            fn run(self) -> Result<(), Impossible> {
+                panic!("intended panic")
+            }
+        }
+
+        fn panicking_helper() -> (Fetch<()>, u32) {
+            (Fetch::new(PanicRequest), line!())
+        }
+
+        let (fetch, line) = panicking_helper();
+        let payload = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let _ = fetch.run();
+        }))
+        .unwrap_err();
+        let msg = payload.downcast_ref::<String>().unwrap();
+        assert!(msg.contains(&format!("{}:{}", file!(), line)), "{}", msg);
+        assert!(msg.ends_with("intended panic"), "{}", msg);
+    }
+
+    #[test]
+    fn test_interpose() {
+        let fetch = lift2(
+            |x: String, ys: Vec<String>| format!("{} {}", x, ys.join(" ")),
+            topics(),
+            fetch! {
+                    ids <- get_post_ids();
+                    ids.into_iter().traverse(|id| Fetch::new(SleepRequest {
+                        name: "post_title",
+                        sleep_duration: 10,
+                        result: format!("post-{}", id.0),
+                    }))
+            },
+        );
+        let prefixed = fetch
+            .interpose(|req: &mut SleepRequest<String>| {
+                req.sleep_duration = 0;
+                req.result.insert_str(0, "tenant/");
+            })
+            .run()
+            .unwrap_or_else(|e| match e {});
+        assert_eq!(
+            prefixed,
+            "tenant/<p>topic 1, topic 2, ...</p>\n tenant/post-1 tenant/post-2"
+        );
+    }
+
+    fn parse_topic_count(topics: String) -> Result<usize, Exception> {
+        topics
+            .rsplit(',')
+            .next()
+            .and_then(|s| s.trim().strip_prefix("topic "))
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| Exception::Msg(format!("cannot parse {}", topics)))
+    }
+
+    #[test]
+    fn test_try_fmap_and_try_bind() {
+        let immediate: Fetch<usize, Exception> =
+            Fetch::pure("topic 1, topic 7".to_string()).try_fmap(parse_topic_count);
+        assert_eq!(immediate.run(), Ok(7));
+
+        let after_round = Fetch::new(SleepRequest {
+            name: "topics",
+            sleep_duration: 10,
+            result: "topic 1, topic 3".to_string(),
+        })
+        .into::<Exception>()
+        .try_bind(|topics| Ok(Fetch::pure(parse_topic_count(topics)? * 2)));
+        assert_eq!(after_round.run(), Ok(6));
+
+        let recovered = catch(
+            topics()
+                .into()
+                .try_fmap(parse_topic_count)
+                .fmap(|n| n.to_string()),
+            |Exception::Msg(msg)| Fetch::pure(msg),
+        );
+        assert_eq!(
+            recovered.run().unwrap_or_else(|e: Impossible| match e {}),
+            "cannot parse <p>topic 1, topic 2, ...</p>\n"
+        );
+    }
+
+    fn chain(rounds: usize, sleep_duration: u64) -> Fetch<usize> {
+        (0..rounds).fold(Fetch::pure(0), |acc, _| {
+            acc.bind(move |n| {
+                Fetch::new(SleepRequest {
+                    name: "chain",
+                    sleep_duration,
+                    result: n + 1,
+                })
+            })
+        })
+    }
+
+    #[test]
+    fn test_run_with_deadline_and_stats() {
+        let outcome = chain(3, 40).run_with(
+            RunOptions::new()
+                .deadline_in(Duration::from_millis(60))
+                .collect_stats(true),
+        );
+        assert_eq!(outcome.result, Err(RunError::DeadlineExceeded));
+        let stats = outcome.stats.unwrap();
+        assert_eq!((stats.rounds, stats.requests), (2, 2));
+        assert!(stats.elapsed >= Duration::from_millis(80));
+
+        let outcome = chain(3, 1).run_with(RunOptions::new());
+        assert_eq!(outcome.result, Ok(3));
+        assert!(outcome.stats.is_none());
+    }
+
+    #[test]
+    fn test_run_with_max_concurrency() {
+        let options = RunOptions::new().max_concurrency(1).collect_stats(true);
+        let outcome = lift2(|a, b| a + b, slow_identity(1), slow_identity(2)).run_with(options);
+        assert_eq!(outcome.result, Ok(3));
+        let stats = outcome.stats.unwrap();
+        assert_eq!((stats.rounds, stats.requests), (1, 2));
+        // both requests shared one thread
+        assert!(stats.elapsed >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_max_concurrency_pool_is_reused() {
+        let options = RunOptions::new().max_concurrency(1);
+        let thread_of_run = || {
+            let fetch = Fetch::<thread::ThreadId>::new_with_key(|| Ok(thread::current().id()), 0);
+            fetch.run_with(options.clone()).result.unwrap()
+        };
+        let first = thread_of_run();
+        assert_ne!(first, thread::current().id());
+        assert_eq!(thread_of_run(), first);
+    }
+
+    #[test]
+    fn test_fold_results() {
+        let fetches: Vec<Fetch<usize, Exception>> = vec![
+            slow_identity(1).into(),
+            throw(Exception::Msg("first".to_string())),
+            slow_identity(2)
+                .into()
+                .bind(|_| throw(Exception::Msg("second".to_string()))),
+            slow_identity(3).into(),
+        ];
+        let report = fold_results(fetches, (0, Vec::new()), |(sum, mut errs), r| match r {
+            Ok(n) => (sum + n, errs),
+            Err(Exception::Msg(msg)) => {
+                errs.push(msg);
+                (sum, errs)
+            }
+        });
+        let outcome = report.run_with(RunOptions::new().collect_stats(true));
+        assert_eq!(
+            outcome.result,
+            Ok((4, vec!["first".to_string(), "second".to_string()]))
+        );
+        assert_eq!(outcome.stats.unwrap().rounds, 1);
+    }
+
+    #[derive(Hash, Clone, PartialEq, Eq)]
+    struct Echo(usize);
+
+    impl Request<usize> for Echo {
+        fn run(self) -> Result<usize, Impossible> {
+            Ok(self.0)
+        }
+    }
+
+    fn echo(n: usize) -> Fetch<usize> {
+        Fetch::new(Echo(n))
+    }
+
+    #[test]
+    fn test_max_distinct_requests() {
+        let crawl = || {
+            fetch! {
+                ids <- (0..3).traverse(echo);
+                _ <- ids.into_iter().traverse(echo);
+                (10..13).traverse(echo)
+            }
+        };
+        let options = RunOptions::new().collect_stats(true);
+        let outcome = crawl().run_with(options.clone().max_distinct_requests(5));
+        assert_eq!(outcome.result, Err(RunError::TooManyRequests { limit: 5 }));
+        // the third round was never dispatched
+        assert_eq!(outcome.stats.unwrap().rounds, 2);
+
+        // the second round repeats the first, so it adds nothing
+        let outcome = crawl().run_with(options.max_distinct_requests(6));
+        assert_eq!(outcome.result, Ok(vec![10, 11, 12]));
+        assert_eq!(outcome.stats.unwrap().requests, 9);
+    }
+
+    #[test]
+    fn test_dyn_request() {
+        let manifest: Vec<Box<dyn DynRequest>> = vec![
+            dyn_request(Echo(1)),
+            dyn_request(Echo(2)),
+            dyn_request(SleepRequest {
+                name: "greeting",
+                sleep_duration: 1,
+                result: "hi".to_string(),
+            }),
+        ];
+        let mut manifest = manifest.into_iter();
+        let numbers = manifest.by_ref().take(2).traverse(Fetch::new_dyn);
+        let greeting = Fetch::new_dyn(manifest.next().unwrap());
+        let page = lift2(
+            |ns: Vec<usize>, g: String| format!("{} {:?}", g, ns),
+            numbers,
+            greeting,
+        );
+        assert_eq!(page.run().unwrap(), "hi [1, 2]");
+
+        let mismatch: Fetch<String, BoxError> = Fetch::new_dyn(dyn_request(Echo(3)));
+        let err = mismatch.run().unwrap_err();
+        let err = err.downcast_ref::<DowncastError>().unwrap();
+        assert_eq!(err.expected, any::type_name::<String>());
+        assert!(err.request.ends_with("Echo"), "{}", err.request);
+    }
+
+    #[derive(Hash, Clone, PartialEq, Eq)]
+    struct JitterRequest(usize);
+
+    impl Request<usize> for JitterRequest {
+        fn run(self) -> Result<usize, Impossible> {
+            thread::sleep(Duration::from_millis(rand::random::<u64>() % 20));
+            Ok(self.0 * 10)
+        }
+    }
+
+    #[test]
+    fn test_results_ignore_completion_order() {
+        let expected: Vec<usize> = (0..16).map(|n| n * 10).collect();
+        for _ in 0..3 {
+            let results = (0..16).traverse(|n| Fetch::new(JitterRequest(n))).run();
+            assert_eq!(results, Ok(expected.clone()));
+        }
+    }
+
+    #[test]
+    fn test_round_budget() {
+        let requests = (0..4).traverse(|n| {
+            Fetch::new(SleepRequest {
+                name: "budgeted",
+                sleep_duration: 80,
+                result: n,
+            })
+        });
+        let total = fetch! {
+            ns <- requests;
+            let sum = ns.iter().sum::<usize>();
+            m <- slow_identity(sum);
+            return (ns, m)
+        };
+        let outcome = total.run_with(
+            RunOptions::new()
+                .max_concurrency(1)
+                .round_budget(Duration::from_millis(100))
+                .collect_stats(true),
+        );
+        assert_eq!(outcome.result, Ok((vec![0, 1, 2, 3], 6)));
+        let stats = outcome.stats.unwrap();
+        assert_eq!(stats.requests, 5);
+        // four requests cannot all start within one budget on one thread
+        assert!(stats.rounds > 2, "{:?}", stats);
+    }
+
+    // fails its first `failures` attempts
+    #[derive(Clone, Debug)]
+    struct FlakyRequest {
+        id: usize,
+        failures: usize,
+        attempts: Arc<sync::atomic::AtomicUsize>,
+    }
+
+    impl FlakyRequest {
+        fn new(id: usize, failures: usize) -> FlakyRequest {
+            FlakyRequest {
+                id,
+                failures,
+                attempts: Arc::new(sync::atomic::AtomicUsize::new(0)),
+            }
+        }
+
+        fn attempts(&self) -> usize {
+            self.attempts.load(sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    impl Hash for FlakyRequest {
+        fn hash<H: hash::Hasher>(&self, state: &mut H) {
+            self.id.hash(state)
+        }
+    }
+
+    impl PartialEq for FlakyRequest {
+        fn eq(&self, other: &Self) -> bool {
+            self.id == other.id
+        }
+    }
+
+    impl Eq for FlakyRequest {}
+
+    impl Request<usize, Exception> for FlakyRequest {
+        fn run(self) -> Result<usize, Exception> {
+            let attempt = self.attempts.fetch_add(1, sync::atomic::Ordering::SeqCst);
+            if attempt < self.failures {
+                Err(Exception::Msg(format!(
+                    "attempt {} of {} failed",
+                    attempt, self.id
+                )))
+            } else {
+                Ok(self.id)
+            }
+        }
+    }
+
+    #[test]
+    fn test_catch_with_retry() {
+        let req = FlakyRequest::new(7, 1);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let (retry, log2) = (req.clone(), log.clone());
+        let fetch = Fetch::new(req.clone()).catch_with_retry(move |Exception::Msg(msg)| {
+            log2.lock().unwrap().push(msg);
+            Fetch::new(retry)
+        });
+        let outcome = fetch.run_with(RunOptions::new().collect_stats(true));
+        assert_eq!(outcome.result, Ok(7));
+        assert_eq!(outcome.stats.unwrap().rounds, 2);
+        assert_eq!(req.attempts(), 2);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["attempt 0 of 7 failed".to_string()]
+        );
+
+        // the retry's own error is not caught again
+        let req = FlakyRequest::new(8, 2);
+        let retry = req.clone();
+        let fetch = Fetch::new(req.clone()).catch_with_retry(move |_| Fetch::new(retry));
+        assert_eq!(
+            fetch.run(),
+            Err(Exception::Msg("attempt 1 of 8 failed".to_string()))
+        );
+        assert_eq!(req.attempts(), 2);
+    }
+
+    impl From<LengthMismatch> for Exception {
+        fn from(e: LengthMismatch) -> Exception {
+            Exception::Msg(e.to_string())
+        }
+    }
+
+    impl From<ResultTooLarge> for Exception {
+        fn from(e: ResultTooLarge) -> Exception {
+            Exception::Msg(e.to_string())
+        }
+    }
+
+    #[derive(Hash, Clone, PartialEq, Eq)]
+    struct RegionRequest {
+        region: &'static str,
+        ids: Vec<usize>,
+    }
+
+    impl Request<Vec<String>, Exception> for RegionRequest {
+        fn run(self) -> Result<Vec<String>, Exception> {
+            let region = self.region;
+            Ok(self
+                .ids
+                .into_iter()
+                .map(|id| format!("{}:{}", region, id))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_group_traverse() {
+        let items = vec![
+            ("eu", 1),
+            ("us", 2),
+            ("eu", 3),
+            ("ap", 4),
+            ("us", 5),
+            ("ap", 6),
+        ];
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let calls2 = calls.clone();
+        let fetch = group_traverse(
+            items.clone(),
+            |(region, _)| *region,
+            move |region, group| {
+                calls2.lock().unwrap().push(region);
+                let ids = group.into_iter().map(|(_, id)| id).collect();
+                Fetch::new(RegionRequest { region, ids })
+            },
+        );
+        let outcome = fetch.run_with(RunOptions::new().collect_stats(true));
+        let expected: Vec<String> = items
+            .iter()
+            .map(|(r, id)| format!("{}:{}", r, id))
+            .collect();
+        assert_eq!(outcome.result, Ok(expected));
+        assert_eq!(*calls.lock().unwrap(), vec!["eu", "us", "ap"]);
+        let stats = outcome.stats.unwrap();
+        assert_eq!((stats.rounds, stats.requests), (1, 3));
+
+        let short = group_traverse(
+            items,
+            |(region, _)| *region,
+            |region, _| Fetch::<_, Exception>::pure(vec![region.to_string()]),
+        );
+        assert_eq!(
+            short.run(),
+            Err(Exception::Msg(
+                "expected 2 results for the group, got 1".to_string()
+            ))
+        );
+    }
+
+    #[cfg(feature = "trace")]
+    struct CaptureLogger;
+
+    #[cfg(feature = "trace")]
+    static CAPTURED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    #[cfg(feature = "trace")]
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.target() == "ruxl::trace"
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                CAPTURED.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[cfg(feature = "trace")]
+    #[test]
+    fn test_trace_values() {
+        // another test may have installed a logger already
+        let _ = log::set_logger(&CaptureLogger);
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let pipeline = || {
+            fetch! {
+                ids <- (1..4).traverse(echo).traced("ids");
+                total <- echo(ids.iter().sum()).fmap(|n| n * 1000).traced("total");
+                return total
+            }
+        };
+        assert_eq!(pipeline().run(), Ok(6000));
+        assert!(CAPTURED.lock().unwrap().is_empty());
+
+        let options = RunOptions::new().trace_values(true).trace_value_len(5);
+        assert!(pipeline().run_with(options).result.is_ok());
+        // records read "<label> at <location>: <value>"
+        let captured: Vec<(String, String)> = CAPTURED
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|record| {
+                let (label, rest) = record.split_once(" at ").unwrap();
+                let (_, value) = rest.rsplit_once(": ").unwrap();
+                (label.to_string(), value.to_string())
+            })
+            .collect();
+        assert_eq!(
+            captured,
+            vec![
+                ("ids".to_string(), "[1, 2...".to_string()),
+                ("total".to_string(), "6000".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_traced_without_tracing() {
+        let traced = echo(1).traced("one").bind(|n| echo(n + 1).traced("two"));
+        assert_eq!(traced.run(), Ok(2));
+    }
+
+    #[test]
+    fn test_observe_value() {
+        let (tx, rx) = sync::mpsc::channel();
+        let ui = thread::spawn(move || rx.iter().collect::<Vec<_>>());
+        let fetch = fetch! {
+            ids <- get_post_ids().observe_value(tx.clone());
+            let n = ids.len();
+            echo(n).observe_value(tx)
+        };
+        assert_eq!(fetch.run(), Ok(2));
+        assert_eq!(
+            ui.join().unwrap(),
+            vec!["[PostId(1), PostId(2)]".to_string(), "2".to_string()]
+        );
+    }
+
+    // holds a sentinel and returns a fresh one, registering both
+    #[derive(Clone)]
+    struct SentinelRequest {
+        id: usize,
+        held: Arc<()>,
+        registry: Arc<Mutex<Vec<sync::Weak<()>>>>,
+    }
+
+    impl Hash for SentinelRequest {
+        fn hash<H: hash::Hasher>(&self, state: &mut H) {
+            self.id.hash(state)
+        }
+    }
+
+    impl PartialEq for SentinelRequest {
+        fn eq(&self, other: &Self) -> bool {
+            self.id == other.id
+        }
+    }
+
+    impl Eq for SentinelRequest {}
+
+    impl Request<Arc<()>> for SentinelRequest {
+        fn run(self) -> Result<Arc<()>, Impossible> {
+            let result = Arc::new(());
+            let mut registry = self.registry.lock().unwrap();
+            registry.push(Arc::downgrade(&self.held));
+            registry.push(Arc::downgrade(&result));
+            Ok(result)
+        }
+    }
+
+    // counts the registered sentinels still alive when it runs
+    #[derive(Clone)]
+    struct ProbeRequest(Arc<Mutex<Vec<sync::Weak<()>>>>);
+
+    impl Hash for ProbeRequest {
+        fn hash<H: hash::Hasher>(&self, _: &mut H) {}
+    }
+
+    impl PartialEq for ProbeRequest {
+        fn eq(&self, _: &Self) -> bool {
+            true
+        }
+    }
+
+    impl Eq for ProbeRequest {}
+
+    impl Request<usize> for ProbeRequest {
+        fn run(self) -> Result<usize, Impossible> {
+            let registry = self.0.lock().unwrap();
+            Ok(registry.iter().filter(|w| w.upgrade().is_some()).count())
+        }
+    }
+
+    #[test]
+    fn test_consumed_values_are_released() {
+        let registry = Arc::new(Mutex::new(Vec::new()));
+        let held = Arc::new(());
+        let fetch = {
+            let (held, registry, probe) = (held.clone(), registry.clone(), registry.clone());
+            fetch! {
+                sentinels <- (0..3).traverse(move |id| Fetch::new(SentinelRequest {
+                    id,
+                    held: held.clone(),
+                    registry: registry.clone(),
+                }));
+                let n = sentinels.len();
+                alive <- Fetch::new(ProbeRequest(probe));
+                return (n, alive)
+            }
+        };
+        drop(held);
+        let (n, alive) = fetch.run().unwrap_or_else(|e| match e {});
+        assert_eq!(n, 3);
+        assert_eq!(registry.lock().unwrap().len(), 6);
+        // requests, status cells and the consumed results of the first round
+        // were all gone by the time the second round ran
+        assert_eq!(alive, 0);
+    }
+
+    #[test]
+    fn test_new_result() {
+        let fetch = lift2(
+            |a, b| (a, b),
+            Fetch::new_result(FlakyRequest::new(1, 1)),
+            Fetch::new_result(FlakyRequest::new(2, 0)),
+        );
+        let outcome = fetch.run_with(
+            RunOptions::new()
+                .collect_stats(true)
+                .max_distinct_requests(2),
+        );
+        assert_eq!(
+            outcome.result,
+            Ok((
+                Err(Exception::Msg("attempt 0 of 1 failed".to_string())),
+                Ok(2)
+            ))
+        );
+        let stats = outcome.stats.unwrap();
+        assert_eq!((stats.rounds, stats.requests), (1, 2));
+
+        // identified exactly like the plain leaf
+        let same = lift2(|a, b| (a, b), echo(5), Fetch::new_result(Echo(5)));
+        let outcome = same.run_with(RunOptions::new().max_distinct_requests(1));
+        assert_eq!(outcome.result, Ok((5, Ok(5))));
+    }
+
+    #[derive(Clone)]
+    struct UserRequest {
+        user: u64,
+        n: usize,
+        starts: Arc<Mutex<Vec<(u64, time::Instant)>>>,
+    }
+
+    impl Hash for UserRequest {
+        fn hash<H: hash::Hasher>(&self, state: &mut H) {
+            (self.user, self.n).hash(state)
+        }
+    }
+
+    impl PartialEq for UserRequest {
+        fn eq(&self, other: &Self) -> bool {
+            (self.user, self.n) == (other.user, other.n)
+        }
+    }
+
+    impl Eq for UserRequest {}
+
+    impl Request<usize> for UserRequest {
+        fn run(self) -> Result<usize, Impossible> {
+            let start = time::Instant::now();
+            self.starts.lock().unwrap().push((self.user, start));
+            Ok(self.n)
+        }
+
+        fn throttle_key(&self) -> Option<u64> {
+            Some(self.user)
+        }
+    }
+
+    #[test]
+    fn test_throttle_per_key() {
+        let starts = Arc::new(Mutex::new(Vec::new()));
+        let starts2 = starts.clone();
+        let request = move |user, n| {
+            Fetch::new(UserRequest {
+                user,
+                n,
+                starts: starts2.clone(),
+            })
+        };
+        let request2 = request.clone();
+        let fetch = fetch! {
+            first <- (0..4).traverse(move |n| request(n as u64 % 2, n));
+            // a second round hitting user 0 again
+            second <- request2(0, 4);
+            return (first, second)
+        };
+        let spacing = Duration::from_millis(40);
+        let outcome = fetch.run_with(RunOptions::new().throttle_per_key(spacing));
+        assert_eq!(outcome.result, Ok((vec![0, 1, 2, 3], 4)));
+
+        let starts = starts.lock().unwrap();
+        for user in 0..2 {
+            let mut times: Vec<_> = starts
+                .iter()
+                .filter(|(u, _)| *u == user)
+                .map(|(_, t)| *t)
+                .collect();
+            times.sort();
+            assert_eq!(times.len(), if user == 0 { 3 } else { 2 });
+            // requests are scheduled `spacing` apart, the first one may
+            // start a little late
+            for pair in times.windows(2) {
+                let slack = Duration::from_millis(5);
+                assert!(
+                    pair[1] - pair[0] + slack >= spacing,
+                    "user {}: {:?}",
+                    user,
+                    pair
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_hedge() {
+        let dispatched = Arc::new(sync::atomic::AtomicUsize::new(0));
+        let count = dispatched.clone();
+        let fallback = move || {
+            Fetch::new_with_key(
+                move || {
+                    count.fetch_add(1, sync::atomic::Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    Ok(100)
+                },
+                "fallback",
+            )
+        };
+        let options = RunOptions::new().collect_stats(true);
+
+        let outcome = chain(6, 30)
+            .hedge(Duration::from_millis(40), fallback)
+            .run_with(options.clone());
+        // the fallback was issued once, after the first 40ms, and its value
+        // won over the primary's, which would have been 6
+        assert_eq!(outcome.result, Ok(100));
+        assert_eq!(dispatched.load(sync::atomic::Ordering::SeqCst), 1);
+        assert!(outcome.stats.unwrap().rounds < 6);
+
+        let outcome = chain(3, 1)
+            .hedge(Duration::from_secs(10), || -> Fetch<usize> {
+                unreachable!()
+            })
+            .run_with(options);
+        assert_eq!(outcome.result, Ok(3));
+        assert_eq!(outcome.stats.unwrap().rounds, 3);
+    }
+
+    fn failing_topics() -> Fetch<String, Exception> {
+        fetch! {
+            n <- slow_identity(1).into();
+            m <- slow_identity(n + 1).into();
+            _ <- slow_identity(m + 1).into();
+            throw(Exception::Msg("topics are down".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_unwrap_or() {
+        let ok: Fetch<String> = topics().into::<Exception>().unwrap_or_default();
+        assert_eq!(ok.run(), Ok("<p>topic 1, topic 2, ...</p>\n".to_string()));
+
+        let outcome = failing_topics()
+            .unwrap_or_default()
+            .run_with(RunOptions::new().collect_stats(true));
+        assert_eq!(outcome.result, Ok(String::new()));
+        assert_eq!(outcome.stats.unwrap().rounds, 3);
+
+        let page: Fetch<String, Exception> = lift2(
+            |side: String, main: String| format!("{}|{}", side, main),
+            failing_topics().unwrap_or("no topics".to_string()).into(),
+            failing_topics()
+                .unwrap_or_else(|Exception::Msg(msg)| msg)
+                .into(),
+        );
+        assert_eq!(page.run(), Ok("no topics|topics are down".to_string()));
+    }
+
+    // a request of round `round`, which takes until 20ms per round have
+    // passed on `clock`
+    fn tick(clock: &Arc<MockClock>, round: u64) -> Fetch<u64> {
+        let clock = clock.clone();
+        Fetch::new_with_key(
+            move || {
+                clock.advance_to(Duration::from_millis(20 * round));
+                Ok(round)
+            },
+            ("tick", round),
+        )
+    }
+
+    // `rounds` ticks one round after another, the first in round `first`
+    fn ticks(clock: &Arc<MockClock>, first: u64, rounds: u64) -> Fetch<u64> {
+        let clock = clock.clone();
+        (first + 1..first + rounds).fold(tick(&clock, first), move |acc, round| {
+            let clock = clock.clone();
+            acc.bind(move |_| tick(&clock, round))
+        })
+    }
+
+    #[test]
+    fn test_timed() {
+        let clock = Arc::new(MockClock::new());
+        let outer_clock = clock.clone();
+        let nested = fetch! {
+            ((x, inner)) <- ticks(&clock, 1, 2).timed_with(clock.clone());
+            y <- tick(&clock, 3);
+            return (x + y, inner)
+        };
+        let ((sum, inner), outer) = nested
+            .timed_with(outer_clock)
+            .run()
+            .unwrap_or_else(|e| match e {});
+        assert_eq!(sum, 5);
+        assert_eq!((inner.rounds, outer.rounds), (2, 3));
+        assert_eq!(inner.elapsed, Duration::from_millis(40));
+        assert_eq!(outer.elapsed, Duration::from_millis(60));
+
+        let clock = Arc::new(MockClock::new());
+        let siblings = lift2(
+            |a, b| (a, b),
+            ticks(&clock, 1, 1).timed_with(clock.clone()),
+            ticks(&clock, 1, 3).timed_with(clock.clone()),
+        );
+        let ((_, short), (_, long)) = siblings.run().unwrap_or_else(|e| match e {});
+        assert_eq!((short.rounds, long.rounds), (1, 3));
+        assert_eq!(short.elapsed, Duration::from_millis(20));
+        assert_eq!(long.elapsed, Duration::from_millis(60));
+    }
+
+    #[test]
+    fn test_traverse_sized() {
+        let results = (0..100)
+            .traverse_sized(100, echo)
+            .run()
+            .unwrap_or_else(|e| match e {});
+        assert_eq!(results, (0..100).collect::<Vec<_>>());
+        assert!(results.capacity() >= 100);
+
+        // an underestimate is only a hint
+        let results = (0..10).traverse_sized(2, echo).run();
+        assert_eq!(results, Ok((0..10).collect()));
+    }
+
+    #[test]
+    fn test_zip_longest() {
+        let zipped = zip_longest((0..3).traverse(echo), (10..12).traverse(echo));
+        let outcome = zipped.run_with(RunOptions::new().collect_stats(true));
+        assert_eq!(
+            outcome.result,
+            Ok(vec![
+                (Some(0), Some(10)),
+                (Some(1), Some(11)),
+                (Some(2), None)
+            ])
+        );
+        assert_eq!(outcome.stats.unwrap().rounds, 1);
+
+        let empty: Fetch<Vec<usize>> = Fetch::pure(vec![]);
+        let zipped = zip_longest(empty, (0..1).traverse(echo));
+        assert_eq!(zipped.run(), Ok(vec![(None, Some(0))]));
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct NotFound(usize);
+
+    impl fmt::Display for NotFound {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{} not found", self.0)
+        }
+    }
+
+    impl error::Error for NotFound {}
+
+    #[derive(Debug, PartialEq)]
+    struct Timeout;
+
+    impl fmt::Display for Timeout {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "timed out")
+        }
+    }
+
+    impl error::Error for Timeout {}
+
+    fn lookup(n: usize) -> Fetch<String, BoxError> {
+        slow_identity(n).into().bind(|n| match n {
+            0 => throw(Box::new(NotFound(n)) as BoxError),
+            1 => throw(Box::new(Timeout) as BoxError),
+            _ => Fetch::pure(format!("item {}", n)),
+        })
+    }
+
+    #[test]
+    fn test_catch_downcast() {
+        let recover_missing =
+            |n| lookup(n).catch_downcast(|NotFound(n)| Fetch::pure(format!("placeholder {}", n)));
+        assert_eq!(recover_missing(0).run().unwrap(), "placeholder 0");
+        assert_eq!(recover_missing(2).run().unwrap(), "item 2");
+        let err = recover_missing(1).run().unwrap_err();
+        assert_eq!(err.downcast_ref::<Timeout>(), Some(&Timeout));
+
+        let ladder = |n| {
+            catch_downcast(
+                lookup(n).catch_downcast(|NotFound(_)| Fetch::pure("missing".to_string())),
+                |Timeout| Fetch::pure("slow".to_string()),
+            )
+        };
+        assert_eq!(ladder(0).run().unwrap(), "missing");
+        assert_eq!(ladder(1).run().unwrap(), "slow");
+    }
+
+    #[test]
+    fn test_traverse_isolate_failures() {
+        let reqs = vec![
+            FlakyRequest::new(0, 0),
+            FlakyRequest::new(1, 1),
+            FlakyRequest::new(2, 2),
+            FlakyRequest::new(3, 0),
+        ];
+        let fetch = reqs
+            .clone()
+            .into_iter()
+            .traverse_isolate_failures(Fetch::new);
+        let outcome = fetch.run_with(RunOptions::new().collect_stats(true));
+        assert_eq!(
+            outcome.result.ok().unwrap(),
+            vec![
+                Ok(0),
+                Ok(1),
+                Err(Exception::Msg("attempt 1 of 2 failed".to_string())),
+                Ok(3),
+            ]
+        );
+        assert_eq!(outcome.stats.unwrap().rounds, 2);
+        let attempts: Vec<usize> = reqs.iter().map(FlakyRequest::attempts).collect();
+        assert_eq!(attempts, vec![1, 2, 2, 1]);
+
+        // no extra round when nothing fails
+        let fetch = (10..14)
+            .map(|id| FlakyRequest::new(id, 0))
+            .traverse_isolate_failures(Fetch::new);
+        let outcome = fetch.run_with(RunOptions::new().collect_stats(true));
+        assert_eq!(outcome.stats.unwrap().rounds, 1);
+    }
+
+    fn fake_sources() -> RunOptions {
+        RunOptions::new()
+            .override_source(|_: &SleepRequest<Vec<PostId>>| Ok(vec![PostId(3)]))
+            .override_source(|req: &SleepRequest<PostInfo>| Ok(req.result.clone()))
+            .override_source(|req: &SleepRequest<PostContent>| {
+                Ok(PostContent(format!("fake {}", req.result.0)))
+            })
+    }
+
+    #[test]
+    fn test_override_source() {
+        let start = time::Instant::now();
+        let options = fake_sources()
+            .override_source(|_: &SleepRequest<String>| Ok("<p>fake pane</p>".to_string()))
+            .strict_overrides(true)
+            .collect_stats(true);
+        let outcome = blog().run_with(options);
+        assert!(start.elapsed() < Duration::from_millis(100));
+        let page = outcome.result.ok().unwrap();
+        assert!(
+            page.contains("<p>world fake A post with id 3</p>"),
+            "{}",
+            page
+        );
+        assert!(page.contains("<div class=\"topics\"><p>fake pane</p></div>"));
+        let stats = outcome.stats.unwrap();
+        assert_eq!((stats.rounds, stats.requests), (3, 5));
+
+        // only the overridden type is substituted
+        let fetch = lift2(|a, b| a + b, echo(1), get_post_ids().fmap(|ids| ids.len()));
+        let outcome = fetch.run_with(fake_sources());
+        assert_eq!(outcome.result, Ok(2));
+    }
+
+    #[test]
+    fn test_strict_overrides() {
+        let outcome = blog().run_with(fake_sources().strict_overrides(true));
+        match outcome.result {
+            Err(RunError::MissingOverride { request }) => {
+                assert!(
+                    request.ends_with("SleepRequest<alloc::string::String>"),
+                    "{}",
+                    request
+                )
+            }
+            _ => panic!("expected a missing override"),
+        }
+    }
+
+    #[test]
+    fn test_run_with_progress() {
+        let plan = chain(3, 1).run_with(RunOptions::new().collect_stats(true));
+        let estimate = plan.stats.unwrap().requests;
+        let mut reports = Vec::new();
+        chain(3, 1)
+            .run_with_progress(estimate, |p| reports.push(p))
+            .unwrap();
+        assert_eq!(reports.len(), 3);
+        assert!(reports.windows(2).all(|w| w[0] < w[1]), "{:?}", reports);
+        assert_eq!(reports.last(), Some(&1.0));
+
+        // an underestimate saturates
+        let mut last = 0.0;
+        chain(2, 1).run_with_progress(1, |p| last = p).unwrap();
+        assert_eq!(last, 1.0);
+    }
+
+    #[test]
+    fn test_shadow() {
+        let mismatches = Arc::new(Mutex::new(Vec::new()));
+        let log = mismatches.clone();
+        // shadow calls run on the rayon pool, not on threads of their own
+        let off_pool = Arc::new(sync::atomic::AtomicBool::new(false));
+        let seen = off_pool.clone();
+        let config = ShadowConfig::new(move |req: &FlakyRequest| {
+            if rayon::current_thread_index().is_none() {
+                seen.store(true, sync::atomic::Ordering::SeqCst);
+            }
+            match req.id {
+                3 => Ok(99),
+                4 => Err(Exception::Msg("unavailable".to_string())),
+                id => Ok(id),
+            }
+        })
+        .sample(|req| req.id % 3 != 2)
+        .on_mismatch(move |m| log.lock().unwrap().push(m.clone()));
+        let counters = config.counters();
+        let fetch = (0..6).traverse(|id| Fetch::new(FlakyRequest::new(id, 0)));
+        let outcome = fetch.run_with(RunOptions::new().shadow(config));
+        assert_eq!(outcome.result, Ok((0..6).collect::<Vec<_>>()));
+
+        let start = time::Instant::now();
+        while counters.finished() < 4 {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "shadow calls did not finish"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!off_pool.load(sync::atomic::Ordering::SeqCst));
+        assert_eq!(counters.compared(), 3);
+        assert_eq!(counters.mismatched(), 1);
+        assert_eq!(counters.failed(), 1);
+        let mismatches = mismatches.lock().unwrap();
+        assert_eq!(mismatches.len(), 1);
+        assert!(
+            mismatches[0].request.contains("id: 3"),
+            "{:?}",
+            mismatches[0]
+        );
+        assert_eq!(
+            (&*mismatches[0].primary, &*mismatches[0].shadow),
+            ("3", "99")
+        );
+    }
+
+    #[test]
+    fn test_map_into() {
+        let content: Fetch<PostContent> = Fetch::pure("a post".to_string()).map_into();
+        assert_eq!(content.run().unwrap().0, "a post");
+        assert_eq!(echo(7).map_into::<Option<usize>>().run(), Ok(Some(7)));
+    }
+
+    // a backend serving one request at a time, each taking 5ms on `clock`,
+    // so that the latency of a wave grows with the number of requests in it
+    fn loaded(clock: &Arc<MockClock>, n: usize) -> Fetch<usize> {
+        let clock = clock.clone();
+        Fetch::new_with_key(
+            move || {
+                clock.advance(Duration::from_millis(5));
+                Ok(n)
+            },
+            ("loaded", n),
+        )
+    }
+
+    #[test]
+    fn test_adaptive_concurrency() {
+        let clock = Arc::new(MockClock::new());
+        let controller = Arc::new(
+            AdaptiveConcurrency::new(Duration::from_millis(12))
+                .bounds(1, 16)
+                .initial_limit(8)
+                .clock(clock.clone()),
+        );
+        let options = RunOptions::new()
+            .adaptive_concurrency(controller.clone())
+            .collect_stats(true);
+
+        // latency rises with concurrency, so the limit comes down
+        let outcome = (0..30)
+            .traverse(move |n| loaded(&clock, n))
+            .run_with(options.clone());
+        assert_eq!(outcome.result, Ok((0..30).collect::<Vec<_>>()));
+        assert!(controller.limit() <= 3, "{}", controller.limit());
+        let stats = outcome.stats.unwrap();
+        assert_eq!(stats.concurrency_limit, Some(controller.limit()));
+        assert_eq!(stats.rounds, 1);
+
+        // and goes back up, across runs, once requests are fast again
+        let outcome = (0..40).traverse(echo).run_with(options);
+        assert_eq!(outcome.result, Ok((0..40).collect::<Vec<_>>()));
+        assert!(controller.limit() >= 8, "{}", controller.limit());
+    }
+
+    #[test]
+    fn test_log_err() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        let fetch = Fetch::new(FlakyRequest::new(4, 1))
+            .log_err(move |Exception::Msg(msg)| log.lock().unwrap().push(msg.clone()));
+        assert_eq!(
+            fetch.run(),
+            Err(Exception::Msg("attempt 0 of 4 failed".to_string()))
+        );
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec!["attempt 0 of 4 failed".to_string()]
+        );
+
+        let seen = Arc::new(Mutex::new(0));
+        let calls = seen.clone();
+        let fetch =
+            Fetch::new(FlakyRequest::new(5, 0)).log_err(move |_| *calls.lock().unwrap() += 1);
+        assert_eq!(fetch.run(), Ok(5));
+        assert_eq!(*seen.lock().unwrap(), 0);
+    }
 
-#[cfg(test)]
-mod tests {
-    #[derive(Hash, PartialEq, Eq, Clone, Debug)]
-    pub enum Exception {
-        Msg(String),
+    #[test]
+    fn test_par_map() {
+        let fetch = (0..100).traverse(echo).par_map(|n| n * n);
+        let outcome = fetch.run_with(RunOptions::new().collect_stats(true));
+        let expected: Vec<usize> = (0..100).map(|n| n * n).collect();
+        assert_eq!(outcome.result, Ok(expected));
+        assert_eq!(outcome.stats.unwrap().rounds, 1);
     }
 
-    use std::time::Duration;
+    #[test]
+    fn test_erase_err() {
+        let missing = || slow_identity(0).into().bind(|n| throw(NotFound(n)));
+        let slow = || throw::<usize, Timeout>(Timeout);
+        let ok = || Fetch::<usize, BoxError>::pure(1);
+        let add = |a: usize, b: usize| a + b;
+        let err = lift2(add, missing().erase_err(), ok()).run().unwrap_err();
+        assert_eq!(err.downcast_ref::<NotFound>(), Some(&NotFound(0)));
+        let err = lift2(add, ok(), slow().erase_err()).run().unwrap_err();
+        assert_eq!(err.downcast_ref::<Timeout>(), Some(&Timeout));
 
-    use super::*;
-    #[derive(Clone, Copy, Debug)]
-    struct PostId(usize);
-    #[derive(Debug, Clone)]
-    struct Date(String);
-    #[derive(Debug, Clone)]
-    struct PostContent(String);
-    #[derive(Debug, Clone)]
-    struct PostInfo {
-        id: PostId,
-        date: Date,
-        topic: String,
+        let missing = throw::<usize, NotFound>(NotFound(2))
+            .erase_err()
+            .erase_err();
+        let recovered = missing.catch_downcast(|NotFound(n)| Fetch::pure(n * 10));
+        assert_eq!(recovered.run().unwrap(), 20);
+
+        let flaky =
+            Fetch::new(FlakyRequest::new(3, 1)).erase_err_with(|Exception::Msg(msg)| msg.into());
+        assert_eq!(
+            flaky.run().unwrap_err().to_string(),
+            "attempt 0 of 3 failed"
+        );
     }
 
-    #[derive(Clone)]
-    struct SleepRequest<T> {
-        name: &'static str,
-        sleep_duration: u64,
-        result: T,
+    #[test]
+    fn test_assert_no_requests() {
+        let pure: Fetch<usize> = lift2(|a, b| a * b, Fetch::pure(6), Fetch::pure(7));
+        assert_eq!(pure.assert_no_requests(), Ok(42));
+        let err = panic::catch_unwind(|| echo(1).fmap(|n| n + 1).assert_no_requests());
+        let err = err.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<String>().unwrap(),
+            "expected a fetch without requests, but it issued 1"
+        );
     }
 
-    // we use name for eq and hash here, only for demonstrating purpose
-    // a real request will not have result stored in it.
-    // Then we will be able to derive Hash and Eq directly
+    // counts how many of its kind have run
+    #[derive(Clone)]
+    struct Tick(usize, Arc<sync::atomic::AtomicUsize>);
 
-    impl<T> Hash for SleepRequest<T> {
+    impl Hash for Tick {
         fn hash<H: hash::Hasher>(&self, state: &mut H) {
-            self.name.hash(state)
+            self.0.hash(state)
         }
     }
 
-    impl<T> PartialEq for SleepRequest<T> {
+    impl PartialEq for Tick {
         fn eq(&self, other: &Self) -> bool {
-            self.name.eq(other.name)
+            self.0 == other.0
         }
     }
 
-    impl<T> Eq for SleepRequest<T> {}
+    impl Eq for Tick {}
 
-    impl<T: Clone> Request<T> for SleepRequest<T> {
-        fn run(self) -> Result<T, Impossible> {
-            thread::sleep(Duration::from_millis(self.sleep_duration));
-            Ok(self.result)
+    impl Request<usize> for Tick {
+        fn run(self) -> Result<usize, Impossible> {
+            self.1.fetch_add(1, sync::atomic::Ordering::SeqCst);
+            Ok(self.0)
         }
     }
 
-    fn get_post_ids() -> Fetch<Vec<PostId>> {
-        Fetch::new(SleepRequest {
-            name: "get_post_ids",
-            sleep_duration: 500,
-            result: vec![PostId(1), PostId(2)],
-        })
+    #[test]
+    fn test_traverse_to_channel() {
+        use sync::atomic::Ordering;
+        let dispatched = Arc::new(sync::atomic::AtomicUsize::new(0));
+        let (tx, rx) = sync::mpsc::sync_channel(1);
+        let counter = dispatched.clone();
+        let consumer = thread::spawn(move || {
+            let mut seen = Vec::new();
+            for n in rx {
+                seen.push((n, counter.load(Ordering::SeqCst)));
+                thread::sleep(Duration::from_millis(5));
+            }
+            seen
+        });
+        let ticks = dispatched.clone();
+        let fetch = (0..8).traverse_to_channel(2, tx, move |n| Fetch::new(Tick(n, ticks.clone())));
+        assert_eq!(fetch.run(), Ok(8));
+        let seen = consumer.join().unwrap();
+        let received: Vec<usize> = seen.iter().map(|&(n, _)| n).collect();
+        assert_eq!(received, (0..8).collect::<Vec<_>>());
+        // at most the buffered result, the one being sent and one chunk
+        // are ahead of the consumer
+        for (k, &(_, dispatched)) in seen.iter().enumerate() {
+            assert!(dispatched <= k + 4, "{:?}", seen);
+        }
+
+        // a hung up receiver stops the traversal
+        let dispatched = Arc::new(sync::atomic::AtomicUsize::new(0));
+        let (tx, rx) = sync::mpsc::sync_channel(4);
+        drop(rx);
+        let ticks = dispatched.clone();
+        let fetch = (0..8).traverse_to_channel(2, tx, move |n| Fetch::new(Tick(n, ticks.clone())));
+        assert_eq!(fetch.run(), Ok(0));
+        assert_eq!(dispatched.load(Ordering::SeqCst), 2);
     }
 
-    fn get_post_info(id: PostId) -> Fetch<PostInfo> {
-        Fetch::new(SleepRequest {
-            name: "get_post_info",
-            sleep_duration: 500,
-            result: PostInfo {
-                id,
-                date: Date("today".to_string()),
-                topic: ["Hello", "world"][id.0 % 2].to_string(),
-            },
-        })
+    #[test]
+    fn test_traverse_within_memory() {
+        let blobs = |max_bytes| {
+            vec![3, 4, 5]
+                .into_iter()
+                .traverse_within_memory(max_bytes, String::len, |n| {
+                    echo(n).into::<Exception>().fmap(|n| "x".repeat(n))
+                })
+        };
+        assert_eq!(blobs(12).run().unwrap(), vec!["xxx", "xxxx", "xxxxx"]);
+        assert_eq!(
+            blobs(8).run(),
+            Err(Exception::Msg(
+                "results take an estimated 12 bytes, over the budget of 8".to_string()
+            ))
+        );
     }
 
-    fn get_post_content(id: PostId) -> Fetch<PostContent, Impossible> {
-        Fetch::new(SleepRequest {
-            name: "get_post_content",
-            sleep_duration: 500,
-            result: PostContent(format!("A post with id {}", id.0)),
+    #[test]
+    fn test_compose() {
+        let scaled = compose(|n| echo(n).fmap(PostId), |id: PostId| echo(id.0 * 10));
+        let outcome = (1..3)
+            .traverse(compose(echo, scaled))
+            .run_with(RunOptions::new().collect_stats(true));
+        assert_eq!(outcome.result, Ok(vec![10, 20]));
+        assert_eq!(outcome.stats.unwrap().rounds, 3);
+    }
+
+    #[test]
+    fn test_run_streaming() {
+        let results: Vec<Result<usize, Exception>> = run_streaming(vec![3, 0, 1, 2], |rounds| {
+            if rounds == 0 {
+                Fetch::new(FlakyRequest::new(0, 1))
+            } else {
+                chain(rounds, 20).into()
+            }
         })
+        .collect();
+        assert_eq!(
+            results,
+            vec![
+                Err(Exception::Msg("attempt 0 of 0 failed".to_string())),
+                Ok(1),
+                Ok(2),
+                Ok(3)
+            ]
+        );
     }
 
-    fn render_posts(it: impl Iterator<Item = (PostInfo, PostContent)>) -> String {
-        it.map(|(info, content)| format!("<p>{} {}</p>", info.topic, content.0))
-            .collect::<Vec<_>>()
-            .join("\n")
+    #[test]
+    fn test_run_streaming_within_a_round() {
+        let start = time::Instant::now();
+        let mut results = run_streaming(vec![("fast", 10), ("slow", 500)], |(name, ms)| {
+            sleep_for(name, ms)
+        });
+        // both requests are issued in the same round, but the fast one is
+        // yielded while the slow one is still running
+        assert_eq!(results.next(), Some(Ok(10)));
+        assert!(start.elapsed() < Duration::from_millis(400));
+        assert_eq!(results.next(), Some(Ok(500)));
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        assert_eq!(results.next(), None);
     }
 
-    fn render_side_pane(posts: String, topics: String) -> String {
-        format!(
-            "<div class=\"topics\">{}</div>\n<div class=\"posts\">{}</div>",
-            topics, posts
-        )
+    #[test]
+    fn test_run_streaming_stops_when_dropped() {
+        let dispatched = Arc::new(sync::atomic::AtomicUsize::new(0));
+        let count = dispatched.clone();
+        // `n` requests one round after another, 20ms each
+        let counted = move |n: usize| {
+            let count = count.clone();
+            (0..n).fold(Fetch::<usize>::pure(0), move |acc, _| {
+                let count = count.clone();
+                acc.bind(move |m| {
+                    Fetch::new_with_key(
+                        move || {
+                            count.fetch_add(1, sync::atomic::Ordering::SeqCst);
+                            thread::sleep(Duration::from_millis(20));
+                            Ok(m + 1)
+                        },
+                        ("counted", m),
+                    )
+                })
+            })
+        };
+        let mut results = run_streaming(vec![0, 10], counted);
+        assert_eq!(results.next(), Some(Ok(0)));
+        drop(results);
+        // long enough for all ten requests, had the run gone on
+        thread::sleep(Duration::from_millis(400));
+        assert!(dispatched.load(sync::atomic::Ordering::SeqCst) <= 1);
     }
 
-    fn render_page(side: String, main: String) -> String {
-        format!(
-            "<html><body><div>{}</div><div>{}</div></body></html>",
-            side, main
-        )
+    #[test]
+    fn test_new_with_key() {
+        // stands in for a connection, which has no identity of its own
+        let conn = Arc::new(Mutex::new(Vec::new()));
+        let lookup = |user: &'static str| {
+            let conn = conn.clone();
+            Fetch::<usize>::new_with_key(
+                move || {
+                    conn.lock().unwrap().push(user);
+                    Ok(user.len())
+                },
+                ("user", user),
+            )
+        };
+        let options = RunOptions::new().max_distinct_requests(2);
+        let same = lift3(
+            |a, b, c| a + b + c,
+            lookup("ann"),
+            lookup("bob"),
+            lookup("ann"),
+        );
+        assert_eq!(same.run_with(options.clone()).result, Ok(9));
+        assert_eq!(conn.lock().unwrap().len(), 3);
+
+        let more = lift3(
+            |a, b, c| a + b + c,
+            lookup("ann"),
+            lookup("bob"),
+            lookup("cy"),
+        );
+        assert_eq!(
+            more.run_with(options).result,
+            Err(RunError::TooManyRequests { limit: 2 })
+        );
+
+        // a key is a different request from a request equal to the key
+        let ann = SleepRequest {
+            name: "ann",
+            sleep_duration: 0,
+            result: 3,
+        };
+        let both = lift2(
+            |a, b| a + b,
+            Fetch::new_with_key(|| Ok(3), ann.clone()),
+            Fetch::new(ann),
+        );
+        assert_eq!(
+            both.run_with(RunOptions::new().max_distinct_requests(1))
+                .result,
+            Err(RunError::TooManyRequests { limit: 1 })
+        );
     }
 
-    fn popular_posts() -> Fetch<String> {
-        Fetch::new(SleepRequest {
-            name: "popular_posts",
-            sleep_duration: 500,
-            result: "<p>popular post 1, popular post 2, ...</p>\n".to_string(),
-        })
+    #[test]
+    fn test_recover_log() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let errors = log.clone();
+        let batch =
+            vec![(1, 0), (2, 1), (3, 0), (4, 1)]
+                .into_iter()
+                .traverse(move |(id, failures)| {
+                    Fetch::new(FlakyRequest::new(id, failures)).recover_log(0, errors.clone())
+                });
+        assert_eq!(batch.run(), Ok(vec![1, 0, 3, 0]));
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                Exception::Msg("attempt 0 of 2 failed".to_string()),
+                Exception::Msg("attempt 0 of 4 failed".to_string()),
+            ]
+        );
     }
 
-    fn topics() -> Fetch<String> {
+    fn sleep_for(name: &'static str, ms: u64) -> Fetch<u64> {
         Fetch::new(SleepRequest {
-            name: "topics",
-            sleep_duration: 500,
-            result: "<p>topic 1, topic 2, ...</p>\n".to_string(),
+            name,
+            sleep_duration: ms,
+            result: ms,
         })
     }
 
-    fn left_pane() -> Fetch<String> {
-        lift2(render_side_pane, popular_posts(), topics())
+    #[test]
+    fn test_critical_path() {
+        // each chain is short, but each round waits for its slow request
+        let fast_then_slow = sleep_for("a1", 5).bind(|_| sleep_for("a2", 40));
+        let slow_then_fast = sleep_for("b1", 40).bind(|_| sleep_for("b2", 5));
+        let fetch = lift2(|a, b| a + b, fast_then_slow, slow_then_fast);
+        let outcome = fetch.run_with(RunOptions::new().collect_critical_path(true));
+        assert_eq!(outcome.result, Ok(45));
+        let stats = outcome.stats.unwrap();
+        let path = stats.critical_path.unwrap();
+        assert_eq!((stats.rounds, path.rounds), (2, 2));
+        assert!(path.duration >= Duration::from_millis(45), "{:?}", path);
+        assert!(path.duration < Duration::from_millis(70), "{:?}", path);
+        assert!(stats.elapsed >= Duration::from_millis(80), "{:?}", stats);
+
+        // deferred requests cost rounds but are not on a chain
+        let fetch = (0..4).traverse(|n| sleep_for(["c0", "c1", "c2", "c3"][n], 20));
+        let outcome = fetch.run_with(
+            RunOptions::new()
+                .round_budget(Duration::from_millis(1))
+                .max_concurrency(1)
+                .collect_critical_path(true),
+        );
+        let stats = outcome.stats.unwrap();
+        assert_eq!(stats.rounds, 4);
+        assert_eq!(stats.critical_path.unwrap().rounds, 1);
     }
 
-    fn get_all_post_info() -> Fetch<Vec<PostInfo>> {
-        fetch! {
-            ids <- get_post_ids();
-            ids.into_iter().traverse(get_post_info)
-        }
+    #[test]
+    fn test_context() {
+        let posts = Fetch::new(FlakyRequest::new(6, 1))
+            .context("fetching posts")
+            .bind(|n| Fetch::pure(n * 2));
+        let side = echo(1).into::<Exception>().context("rendering side pane");
+        let page = lift2(|a, b| a + b, side, posts).add_context("rendering page");
+        let err = page.run().unwrap_err();
+        assert_eq!(err.context, vec!["rendering page", "fetching posts"]);
+        assert_eq!(
+            err.error,
+            Exception::Msg("attempt 0 of 6 failed".to_string())
+        );
+
+        let db = throw::<(), String>("db error".to_string())
+            .context("fetching posts")
+            .add_context("rendering page");
+        assert_eq!(
+            db.run().unwrap_err().to_string(),
+            "rendering page: fetching posts: db error"
+        );
     }
 
-    #[derive(Hash, Clone, PartialEq, Eq)]
-    struct RandomCrashRequest<T, E> {
-        err: E,
-        result: T,
+    #[test]
+    fn test_run_independent() {
+        let fetches = vec![
+            Fetch::new(FlakyRequest::new(1, 0)),
+            Fetch::new(FlakyRequest::new(2, 1)),
+            echo(3).into(),
+        ];
+        assert_eq!(
+            run_independent(fetches),
+            vec![
+                Ok(1),
+                Err(Exception::Msg("attempt 0 of 2 failed".to_string())),
+                Ok(3)
+            ]
+        );
     }
 
-    impl<T, E> Request<T, E> for RandomCrashRequest<T, E>
-    where
-        T: Hash + Eq + Clone,
-        E: Hash + Eq + Clone,
-    {
-        fn run(self) -> Result<T, E> {
-            if !rand::random::<bool>() {
-                Err(self.err)
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "independent fetches took 2 rounds instead of one")]
+    fn test_run_independent_sequential() {
+        run_independent(vec![echo(1), echo(1).bind(echo)]);
+    }
+
+    #[test]
+    fn test_time_batcher() {
+        let batcher = TimeBatcher::new(Duration::from_millis(100), 3);
+        let replies: Vec<_> = (0..3)
+            .map(|n| batcher.submit(move || echo(n).fmap(|n| n * 2)))
+            .collect();
+        let results: Vec<_> = replies.iter().map(|rx| rx.recv().unwrap()).collect();
+        assert_eq!(results, vec![Ok(0), Ok(2), Ok(4)]);
+        assert_eq!(batcher.batches(), 1);
+
+        // a batch that never fills up is flushed by its window
+        let start = time::Instant::now();
+        let rx = batcher.submit(|| echo(7));
+        assert_eq!(rx.recv().unwrap(), Ok(7));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(batcher.batches(), 2);
+
+        // and dropping the batcher flushes what is left
+        let batcher = TimeBatcher::new(Duration::from_secs(60), 10);
+        let thrown = batcher.submit(|| Fetch::new(FlakyRequest::new(1, 1)));
+        drop(batcher);
+        assert_eq!(
+            thrown.recv().unwrap(),
+            Err(Exception::Msg("attempt 0 of 1 failed".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let non_empty = |topics: &String| {
+            if topics.is_empty() {
+                Err(Exception::Msg("no topics".to_string()))
             } else {
-                Ok(self.result)
+                Ok(())
             }
-        }
+        };
+        let fetched = Fetch::new(SleepRequest {
+            name: "topics",
+            sleep_duration: 10,
+            result: "topic 1, topic 3".to_string(),
+        })
+        .into();
+        assert_eq!(
+            fetched.validate(non_empty).run(),
+            Ok("topic 1, topic 3".to_string())
+        );
+        let empty: Fetch<String, Exception> = Fetch::pure(String::new());
+        assert_eq!(
+            empty.validate(non_empty).run(),
+            Err(Exception::Msg("no topics".to_string()))
+        );
     }
 
-    fn random_crash_page() -> Fetch<String, Exception> {
-        Fetch::new(RandomCrashRequest {
-            err: Exception::Msg("Intended error :P".to_string()),
-            result: "".to_string(),
-        })
+    #[derive(Default)]
+    struct RecordingExporter {
+        rounds: Mutex<Vec<usize>>,
+        requests: Mutex<Vec<(&'static str, bool)>>,
+        runs: Mutex<Vec<Duration>>,
     }
 
-    fn main_pane() -> Fetch<String> {
-        // get_all_post_info().bind(|posts| {
-        //     let posts2 = posts.clone();
-        //     posts
-        //         .into_iter()
-        //         .map(|post| post.id)
-        //         .map(get_post_content)
-        //         .sequence()
-        //         .bind(|content| {
-        //             let rendered = render_posts(posts2.into_iter().zip(content.into_iter()));
-        //             Fetch::<String>::pure(rendered)
-        //         })
-        // })
-        fetch! {
-            posts <- get_all_post_info();
-            let posts2 = posts.clone();
-            content <- posts.into_iter().traverse(|post| get_post_content(post.id));
-            let rendered = render_posts(posts2.into_iter().zip(content.into_iter()));
-            return rendered
+    impl MetricsExporter for RecordingExporter {
+        fn record_round(&self, requests: usize) {
+            self.rounds.lock().unwrap().push(requests);
         }
-    }
 
-    fn error_page(e: Exception) -> Fetch<String> {
-        match e {
-            // Exception::HttpError(err_code) => Fetch::pure(format!("<h1> HttpError: {}", err_code)),
-            Exception::Msg(msg) => Fetch::pure(format!(
-                "An error occured ... but you received this message: {}",
-                msg
-            )),
-            // Exception::OutOfMemory => Fetch::pure("Ooof! Out Of Memory!".into()),
-            // Exception::Timeout => Fetch::pure("TvT Timeout!".into()),
+        fn record_request(&self, request: &'static str, _latency: Duration, ok: bool) {
+            self.requests.lock().unwrap().push((request, ok));
+        }
+
+        fn record_duration(&self, elapsed: Duration) {
+            self.runs.lock().unwrap().push(elapsed);
         }
     }
 
-    fn blog() -> Fetch<String> {
-        lift2(render_page, left_pane(), main_pane())
+    #[test]
+    fn test_run_with_exporter() {
+        let exporter = Arc::new(RecordingExporter::default());
+        let flaky = Fetch::new(FlakyRequest::new(1, 1))
+            .try_fetch()
+            .fmap(|res| res.is_err());
+        let fetch = lift2(|n, failed| (n, failed), echo(1), flaky.into())
+            .bind(|(n, failed)| echo(n + 1).fmap(move |m| (m, failed)));
+        assert_eq!(fetch.run_with_exporter(exporter.clone()), Ok((2, true)));
+
+        assert_eq!(*exporter.rounds.lock().unwrap(), vec![2, 1]);
+        let mut requests = exporter.requests.lock().unwrap().clone();
+        requests.sort();
+        let echo_name = any::type_name::<Echo>();
+        let flaky_name = any::type_name::<FlakyRequest>();
+        let mut expected = vec![(echo_name, true), (echo_name, true), (flaky_name, false)];
+        expected.sort();
+        assert_eq!(requests, expected);
+        assert_eq!(exporter.runs.lock().unwrap().len(), 1);
     }
 
-    fn blog_with_crash() -> Fetch<String, Exception> {
-        lift3(
-            |x, y, z| format!("{}<br>{}", z, render_page(y, x)),
-            left_pane().into(),
-            main_pane().into(),
-            random_crash_page(),
+    #[test]
+    fn test_lift_with_recovery() {
+        let fetch = lift3_with_recovery(
+            |a, b, c| (a, b, c),
+            (echo(1), |e: Impossible| match e {}),
+            (Fetch::new(FlakyRequest::new(2, 1)), |_| 0),
+            (Fetch::new(FlakyRequest::new(3, 0)), |_| 0),
+        );
+        let outcome = fetch.run_with(RunOptions::new().collect_stats(true));
+        assert_eq!(outcome.result, Ok((1, 0, 3)));
+        assert_eq!(outcome.stats.unwrap().rounds, 1);
+    }
+
+    #[test]
+    fn test_erase_result() {
+        let handlers: Vec<Fetch<Box<dyn any::Any>>> = vec![
+            echo(1).erase_result(),
+            echo(2).fmap(|n| n.to_string()).erase_result(),
+        ];
+        let mut erased = handlers.into_iter().sequence().run().unwrap();
+        let text = erased.pop().unwrap();
+        assert_eq!(*text.downcast::<String>().unwrap(), "2");
+
+        let number = echo(3).erase_result().downcast::<usize>();
+        assert_eq!(number.run().map(Result::ok), Ok(Some(3)));
+        let mismatched = echo(4).erase_result().downcast::<String>();
+        assert!(mismatched.run().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_compare_sources() {
+        let mismatches = Arc::new(Mutex::new(Vec::new()));
+        let log = mismatches.clone();
+        let options = RunOptions::new().compare_sources(
+            |req: &Echo| Ok(req.0),
+            |req: &Echo| Ok(if req.0 == 2 { 20 } else { req.0 }),
+            move |req: &Echo, old: &Result<usize, Impossible>, new| {
+                if old != new {
+                    log.lock().unwrap().push(req.0);
+                }
+            },
+        );
+        let fetch = vec![1, 2, 3].into_iter().traverse(echo);
+        assert_eq!(fetch.run_with(options).result, Ok(vec![1, 2, 3]));
+        assert_eq!(*mismatches.lock().unwrap(), vec![2]);
+
+        // both sources run on the pool, not on the thread driving the run
+        let slow = |req: &Echo| {
+            thread::sleep(Duration::from_millis(100));
+            Ok(req.0)
+        };
+        let options =
+            RunOptions::new()
+                .max_concurrency(4)
+                .compare_sources(slow, slow, |_, _, _| {});
+        let start = time::Instant::now();
+        let fetch = (0..4).traverse(echo);
+        assert_eq!(fetch.run_with(options).result, Ok(vec![0, 1, 2, 3]));
+        assert!(start.elapsed() < Duration::from_millis(400));
+    }
+
+    #[test]
+    fn test_expand() {
+        let tree = |&n: &usize| lift2(|a, b| vec![a, b], echo(2 * n), echo(2 * n + 1));
+        let outcome = expand(vec![1], 2, tree).run_with(RunOptions::new().collect_stats(true));
+        assert_eq!(outcome.result, Ok(vec![1, 2, 3, 4, 5, 6, 7]));
+        assert_eq!(outcome.stats.unwrap().rounds, 2);
+
+        let cycle = |&n: &usize| echo((n + 1) % 3).fmap(|m| vec![m]);
+        let outcome = expand(vec![0], 10, cycle).run_with(RunOptions::new().collect_stats(true));
+        assert_eq!(outcome.result, Ok(vec![0, 1, 2]));
+        assert_eq!(outcome.stats.unwrap().rounds, 3);
+
+        assert_eq!(expand(vec![1, 1], 0, tree).run(), Ok(vec![1]));
+    }
+
+    #[test]
+    fn test_traverse_sorted_by() {
+        let fetch = vec![3, 12, 5, 21]
+            .into_iter()
+            .traverse_sorted_by(echo, |n| n % 10);
+        let outcome = fetch.run_with(RunOptions::new().collect_stats(true));
+        assert_eq!(outcome.result, Ok(vec![21, 12, 3, 5]));
+        assert_eq!(outcome.stats.unwrap().rounds, 1);
+    }
+
+    #[test]
+    fn test_rewritten_requests_are_rekeyed() {
+        let limited = || RunOptions::new().max_distinct_requests(1);
+        let fetch = (0..5).traverse(echo).interpose(|req: &mut Echo| req.0 = 7);
+        assert_eq!(fetch.run_with(limited()).result, Ok(vec![7; 5]));
+        let options = limited().intercept_requests(|req: &mut Echo| req.0 = 7);
+        let fetch = (0..5).traverse(echo);
+        assert_eq!(fetch.run_with(options).result, Ok(vec![7; 5]));
+    }
+
+    #[test]
+    fn test_intercept_requests() {
+        let options = RunOptions::new().intercept_requests(|req: &mut Echo| req.0 *= 10);
+        let fetch = echo(1).bind(|n| lift2(|a, b| a + b, echo(n), Fetch::pure(n)));
+        assert_eq!(fetch.run_with(options).result, Ok(110));
+    }
+
+    #[test]
+    fn test_accumulate_progress() {
+        let counter = Arc::new(sync::atomic::AtomicUsize::new(0));
+        let items = counter.clone();
+        let fetch =
+            (1..=3).traverse(move |rounds| chain(rounds, 0).accumulate_progress(items.clone()));
+        let mut seen = Vec::new();
+        let res = fetch.run_with_progress(6, |_| {
+            seen.push(counter.load(sync::atomic::Ordering::SeqCst))
+        });
+        assert_eq!(res, Ok(vec![1, 2, 3]));
+        assert_eq!(seen, vec![0, 1, 2]);
+        assert_eq!(counter.load(sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_map_err_into() {
+        let too_large = || ResultTooLarge {
+            max_bytes: 1,
+            size: 2,
+        };
+        let fetch = echo(1)
+            .into()
+            .bind(move |_| throw::<usize, _>(too_large()))
+            .map_err_into::<Exception>();
+        assert_eq!(fetch.run(), Err(Exception::from(too_large())));
+    }
+
+    #[test]
+    fn test_race_ok() {
+        let fetches = vec![
+            Fetch::new(FlakyRequest::new(1, 1)),
+            echo(5).into().bind(|n| echo(n + 1).into()),
+            Fetch::new(FlakyRequest::new(2, 0)).bind(|n| throw(Exception::Msg(n.to_string()))),
+        ];
+        let outcome = race_ok(fetches).run_with(RunOptions::new().collect_stats(true));
+        assert_eq!(outcome.result, Ok(6));
+        assert_eq!(outcome.stats.unwrap().rounds, 2);
+
+        let fetches = vec![
+            echo(3)
+                .into()
+                .bind(|n| throw::<usize, _>(Exception::Msg(n.to_string()))),
+            Fetch::new(FlakyRequest::new(4, 1)),
+        ];
+        assert_eq!(
+            race_ok(fetches).run(),
+            Err(vec![
+                Exception::Msg("3".to_string()),
+                Exception::Msg("attempt 0 of 4 failed".to_string()),
+            ])
+        );
+        assert_eq!(
+            race_ok::<usize, Exception>(Vec::new()).run(),
+            Err(Vec::new())
+        );
+    }
+
+    #[test]
+    fn test_run_with_detailed_stats() {
+        let fetch = lift2(
+            |a, b| a + b,
+            echo(1),
+            Fetch::new(FlakyRequest::new(2, 0)).unwrap_or(0),
         )
+        .bind(|n| vec![n, n + 1].into_iter().traverse(echo));
+        let (result, detailed) = fetch.run_with_detailed_stats();
+        assert_eq!(result, Ok(vec![3, 4]));
+        let echo_name = any::type_name::<Echo>();
+        let flaky_name = any::type_name::<FlakyRequest>();
+        assert_eq!(
+            detailed.rounds,
+            vec![
+                collections::HashMap::from([(echo_name, 1), (flaky_name, 1)]),
+                collections::HashMap::from([(echo_name, 2)]),
+            ]
+        );
+        assert_eq!(detailed.stats.rounds, 2);
+        assert_eq!(detailed.stats.round_breakdown, None);
     }
 
     #[test]
-    fn run_blog() {
-        let start_time = time::Instant::now();
-        let blog = blog();
-        let blog = blog;
-        println!("{}", start_time.elapsed().as_millis());
+    fn test_then_if_ok() {
+        let downstream = |n: usize| Fetch::new(FlakyRequest::new(n + 10, 0));
+        let ok = Fetch::new(FlakyRequest::new(1, 0)).then_if_ok(downstream, 0);
+        assert_eq!(ok.run(), Ok(11));
+        let degraded = Fetch::new(FlakyRequest::new(2, 1)).then_if_ok(downstream, 0);
+        assert_eq!(degraded.run(), Ok(0));
+        let failing = Fetch::new(FlakyRequest::new(3, 0))
+            .then_if_ok(|n| Fetch::new(FlakyRequest::new(n + 10, 1)), 0);
+        assert_eq!(
+            failing.run(),
+            Err(Exception::Msg("attempt 0 of 13 failed".to_string()))
+        );
+    }
 
-        let _result = blog.run();
-        println!("{}", start_time.elapsed().as_millis());
+    #[test]
+    fn test_budget() {
+        let fan_out = || (0..5).traverse(echo);
+        assert_eq!(fan_out().budget(5).run(), Ok(vec![0, 1, 2, 3, 4]));
+        let outcome = fan_out()
+            .budget(4)
+            .run_with(RunOptions::new().collect_stats(true));
+        assert_eq!(
+            outcome.result,
+            Err(RunError::Thrown(BudgetOr::Exceeded { max: 4 }))
+        );
+        assert_eq!(outcome.stats.unwrap().requests, 0);
+
+        // the budget covers every round of the subtree, not the rest of the run
+        let bounded = lift2(|a, b| (a, b), chain(3, 0).budget(2), echo(1).into());
+        assert_eq!(bounded.run(), Err(BudgetOr::Exceeded { max: 2 }));
+        let thrown = Fetch::new(FlakyRequest::new(1, 1)).budget(1);
+        assert_eq!(
+            thrown.run(),
+            Err(BudgetOr::Inner(Exception::Msg(
+                "attempt 0 of 1 failed".to_string()
+            )))
+        );
     }
+
     #[test]
-    fn test_random_crash() {
-        let blog_with_crash = catch(blog_with_crash(), error_page);
-        match blog_with_crash.run() {
-            Ok(result) => println!("{}", result),
-            Err(e) => match e {},
-        }
+    fn test_tee() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = log.clone();
+        let fetch = echo(1)
+            .tee(move |n| sink.lock().unwrap().push(*n))
+            .bind(|n| echo(n + 1));
+        assert_eq!(fetch.run(), Ok(2));
+        assert_eq!(*log.lock().unwrap(), vec![1]);
+
+        let (tx, rx) = sync::mpsc::channel();
+        let fetch = (1..=3).traverse(move |n| echo(n).tee_clone(tx.clone()));
+        assert_eq!(fetch.run(), Ok(vec![1, 2, 3]));
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        let thrown = Fetch::new(FlakyRequest::new(1, 1)).tee(|_| panic!("tee on error"));
+        assert!(thrown.run().is_err());
+    }
+
+    fn slow_identity(x: usize) -> Fetch<usize> {
+        Fetch::new(SleepRequest {
+            name: "slow_identity",
+            sleep_duration: 50,
+            result: x,
+        })
+    }
+
+    #[test]
+    fn test_result_or_partial() {
+        // the first chunk always starts and outlives the deadline
+        let deadline = time::Instant::now() + Duration::from_millis(20);
+        let (done, rest) = (0..5)
+            .result_or_partial(2, deadline, slow_identity)
+            .unwrap_or_else(|e| match e {});
+        assert_eq!(done, vec![0, 1]);
+        assert_eq!(rest.run().unwrap_or_else(|e| match e {}), vec![2, 3, 4]);
+
+        let (done, rest) = (0..3)
+            .result_or_partial(2, time::Instant::now(), slow_identity)
+            .unwrap_or_else(|e| match e {});
+        assert!(done.is_empty());
+        assert_eq!(rest.run().unwrap_or_else(|e| match e {}), vec![0, 1, 2]);
+
+        // a throwing chunk keeps the chunks before it, and resuming picks up
+        // from the start of the failing chunk
+        let deadline = time::Instant::now() + Duration::from_secs(60);
+        let failed = rc::Rc::new(cell::Cell::new(false));
+        let failure = (0..7)
+            .result_or_partial(2, deadline, move |n| {
+                if n == 3 && !failed.replace(true) {
+                    throw(Exception::Msg("three".to_string()))
+                } else {
+                    Fetch::pure(n)
+                }
+            })
+            .unwrap_err();
+        assert_eq!(failure.error, Exception::Msg("three".to_string()));
+        assert_eq!(failure.done, vec![0, 1]);
+        assert!(format!("{:?}", failure).contains("done: [0, 1]"));
+        let mut resumed = failure.done;
+        resumed.extend(failure.rest.run().unwrap());
+        assert_eq!(resumed, (0..7).collect::<Vec<_>>());
     }
 }
@@ -0,0 +1,33 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A sink for metrics streamed while a run progresses, for instance to
+/// forward them to a metrics system. Attached to a run with
+/// `RunOptions::metrics_exporter` or `Fetch::run_with_exporter`.
+///
+/// Requests are reported from the threads that ran them, concurrently with
+/// each other, so implementations must synchronise internally. Every method
+/// does nothing by default.
+pub trait MetricsExporter: Send + Sync {
+    /// Called before each round is dispatched, with the number of requests
+    /// it holds, including those deferred from the previous round.
+    fn record_round(&self, _requests: usize) {}
+
+    /// Called once a request finishes, with its type name, how long it ran
+    /// and whether it succeeded. Requests answered by `override_source` are
+    /// not run and thus not reported.
+    fn record_request(&self, _request: &'static str, _latency: Duration, _ok: bool) {}
+
+    /// Called once the run finishes, with its wall time.
+    fn record_duration(&self, _elapsed: Duration) {}
+}
+
+#[derive(Clone)]
+pub(crate) struct Exporter(pub(crate) Arc<dyn MetricsExporter>);
+
+impl fmt::Debug for Exporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MetricsExporter")
+    }
+}
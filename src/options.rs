@@ -0,0 +1,551 @@
+use std::any::{self, Any, TypeId};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::adaptive::AdaptiveConcurrency;
+use crate::critical::{CriticalGuard, CriticalPath};
+use crate::metrics::Exporter;
+#[cfg(feature = "trace")]
+use crate::trace::TraceGuard;
+use crate::{
+    vec_merge, AbsRequest, Fetch, MetricsExporter, PooledSource, ReqResult, Request, ShadowConfig,
+    Source,
+};
+
+/// Settings for a single execution of a `Fetch`, consumed by `Fetch::run_with`.
+///
+/// Options are plain values and cheap to clone, so one template can be
+/// reused for many runs.
+#[derive(Clone, Debug, Default)]
+pub struct RunOptions {
+    pool: Option<Arc<rayon::ThreadPool>>,
+    deadline: Option<Duration>,
+    round_budget: Option<Duration>,
+    throttle_spacing: Option<Duration>,
+    max_distinct_requests: Option<usize>,
+    collect_stats: bool,
+    #[cfg(feature = "trace")]
+    trace_values: bool,
+    #[cfg(feature = "trace")]
+    trace_value_len: Option<usize>,
+    sources: PerType,
+    strict_overrides: bool,
+    shadows: PerType,
+    compared: PerType,
+    adaptive: Option<Arc<AdaptiveConcurrency>>,
+    collect_critical_path: bool,
+    collect_round_breakdown: bool,
+    exporter: Option<Exporter>,
+    interceptors: PerType,
+}
+
+// Rewrites a request of the type it was registered for, type-erased so the
+// run loop can apply it without knowing the type.
+type Interceptor = Box<dyn Fn(&mut dyn Any) + Send + Sync>;
+
+// Type-erased settings for individual request types, such as the `Source`s
+// registered with `override_source`, together with the types' names.
+#[derive(Clone, Default)]
+struct PerType(HashMap<TypeId, (&'static str, Arc<dyn Any + Send + Sync>)>);
+
+impl PerType {
+    fn insert<R: 'static>(&mut self, setting: impl Any + Send + Sync) {
+        self.0.insert(
+            TypeId::of::<R>(),
+            (any::type_name::<R>(), Arc::new(setting)),
+        );
+    }
+}
+
+impl fmt::Debug for PerType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(self.0.values().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+impl RunOptions {
+    pub fn new() -> RunOptions {
+        RunOptions::default()
+    }
+
+    /// Runs the requests of each round on at most `n` threads.
+    ///
+    /// The threads are started here, once, and shared by every run using
+    /// these options or a clone of them.
+    pub fn max_concurrency(mut self, n: usize) -> RunOptions {
+        assert!(n > 0, "max_concurrency must be positive");
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(n)
+            .build()
+            .expect("failed to build the request thread pool");
+        self.pool = Some(Arc::new(pool));
+        self
+    }
+
+    /// Aborts the run with `RunError::DeadlineExceeded` if a round would
+    /// start more than `timeout` after the run began. A round that has
+    /// already started is always allowed to finish.
+    pub fn deadline_in(mut self, timeout: Duration) -> RunOptions {
+        self.deadline = Some(timeout);
+        self
+    }
+
+    /// Gives each round a time budget. Requests that have not started when
+    /// the budget runs out are deferred to the next round instead of being
+    /// dropped, and anything depending on them stays blocked until they have
+    /// run. Requests that have started always finish.
+    pub fn round_budget(mut self, budget: Duration) -> RunOptions {
+        self.round_budget = Some(budget);
+        self
+    }
+
+    /// Spaces out requests that share a `Request::throttle_key` so that
+    /// each is scheduled to start at least `spacing` after the previous one
+    /// with the same key, within a round and across rounds. Requests with different keys
+    /// still run in parallel. A throttled request waits on the thread it
+    /// was scheduled on.
+    pub fn throttle_per_key(mut self, spacing: Duration) -> RunOptions {
+        self.throttle_spacing = Some(spacing);
+        self
+    }
+
+    /// Aborts the run with `RunError::TooManyRequests` instead of dispatching
+    /// a round that would bring the number of distinct requests issued over
+    /// the whole run above `max`. Requests are told apart by type and hash.
+    pub fn max_distinct_requests(mut self, max: usize) -> RunOptions {
+        self.max_distinct_requests = Some(max);
+        self
+    }
+
+    /// Reports `RunStats` in the `RunOutcome`.
+    pub fn collect_stats(mut self, collect: bool) -> RunOptions {
+        self.collect_stats = collect;
+        self
+    }
+
+    /// Reports the `CriticalPath` of the run in its `RunStats`, to tell the
+    /// rounds forced by `bind` apart from those a run spends waiting, for
+    /// instance on deferred requests. Implies `collect_stats`.
+    ///
+    /// A request is taken to depend on the results read, in the step that
+    /// issued it, by the fetch issuing it, and on nothing read by fetches
+    /// combined with it through `ap`, the lift functions or `traverse`.
+    pub fn collect_critical_path(mut self, collect: bool) -> RunOptions {
+        self.collect_critical_path = collect;
+        self
+    }
+
+    /// Streams metrics about the run to `exporter` as it progresses.
+    pub fn metrics_exporter(mut self, exporter: Arc<dyn MetricsExporter>) -> RunOptions {
+        self.exporter = Some(Exporter(exporter));
+        self
+    }
+
+    /// Reports in `RunStats::round_breakdown` how many requests of each type
+    /// every round dispatched, to check that sources combined with `ap` or
+    /// `traverse` really share rounds. Implies `collect_stats`.
+    pub fn collect_round_breakdown(mut self, collect: bool) -> RunOptions {
+        self.collect_round_breakdown = collect;
+        self
+    }
+
+    /// Logs the values flowing through `Fetch::traced` points. Available
+    /// with the `trace` feature.
+    #[cfg(feature = "trace")]
+    pub fn trace_values(mut self, trace: bool) -> RunOptions {
+        self.trace_values = trace;
+        self
+    }
+
+    /// Truncates traced values to `len` bytes of their `Debug` rendering.
+    /// Defaults to 128.
+    #[cfg(feature = "trace")]
+    pub fn trace_value_len(mut self, len: usize) -> RunOptions {
+        self.trace_value_len = Some(len);
+        self
+    }
+
+    /// Applies `f` to every request of type `R` issued during the run,
+    /// before it is dispatched, for instance to point requests at a test
+    /// backend. Like `Fetch::interpose`, but for the whole run and without
+    /// touching the fetch; requests are rewritten before being overridden
+    /// or shadowed. A rewritten request is counted by
+    /// `max_distinct_requests` and throttled as the request it has become.
+    pub fn intercept_requests<R: 'static>(
+        mut self,
+        f: impl Fn(&mut R) + Send + Sync + 'static,
+    ) -> RunOptions {
+        let interceptor: Interceptor = Box::new(move |request| {
+            if let Some(request) = request.downcast_mut::<R>() {
+                f(request)
+            }
+        });
+        self.interceptors.insert::<R>(interceptor);
+        self
+    }
+
+    /// Answers every request of type `R` issued during the run with
+    /// `source` instead of its `Request::run`, for instance to substitute
+    /// test doubles for real clients. Requests of other types run normally.
+    ///
+    /// Overridden requests are answered on the thread driving the run, as
+    /// they are drawn from each round, so the substitution also covers
+    /// requests issued by retries, hedges and traversals. They are counted
+    /// in `RunStats` like any other request but are never throttled.
+    /// Requests lifted with `Fetch::new_dyn` cannot be overridden.
+    pub fn override_source<R, T, E>(
+        mut self,
+        source: impl Fn(&R) -> Result<T, E> + Send + Sync + 'static,
+    ) -> RunOptions
+    where
+        R: Request<T, E> + 'static,
+        T: 'static,
+        E: 'static,
+    {
+        let source: Source<R, T, E> = Box::new(source);
+        self.sources.insert::<R>(source);
+        self
+    }
+
+    /// Answers every request of type `R` with both `primary` and
+    /// `candidate`, for instance the old and new implementations of a data
+    /// source being migrated, and passes the request and both results to
+    /// `compare`, typically to log mismatches. The fetch only sees the
+    /// primary's result.
+    ///
+    /// Both sources are called where the request would have run, side by
+    /// side with `rayon::join`, so requests still run in parallel and the
+    /// round waits for the slower of the two. Unlike `shadow`, the round
+    /// does wait for the candidate. Requests answered by `override_source`
+    /// are not compared, and compared requests are not shadowed; they count
+    /// as overridden for `strict_overrides`.
+    pub fn compare_sources<R, T, E>(
+        mut self,
+        primary: impl Fn(&R) -> Result<T, E> + Send + Sync + 'static,
+        candidate: impl Fn(&R) -> Result<T, E> + Send + Sync + 'static,
+        compare: impl Fn(&R, &Result<T, E>, &Result<T, E>) + Send + Sync + 'static,
+    ) -> RunOptions
+    where
+        R: Request<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        let source: Source<R, T, E> = Box::new(move |request: &R| {
+            let (a, b) = (request.clone(), request.clone());
+            let (primary, candidate) = (&primary, &candidate);
+            let (res, other) = rayon::join(move || primary(&a), move || candidate(&b));
+            compare(request, &res, &other);
+            res
+        });
+        let source: PooledSource<R, T, E> = Arc::new(source);
+        self.compared.insert::<R>(source);
+        self
+    }
+
+    /// Aborts the run with `RunError::MissingOverride` instead of running
+    /// a request that no `override_source` answers, guaranteeing that the
+    /// run never reaches a real source.
+    pub fn strict_overrides(mut self, strict: bool) -> RunOptions {
+        self.strict_overrides = strict;
+        self
+    }
+
+    /// Dispatches each round in waves sized by `controller`, which adapts
+    /// to the latencies it observes. Combined with `round_budget`, the budget
+    /// is checked between waves. Waves still run on the pool set up by
+    /// `max_concurrency`, if any, which caps the requests actually in flight.
+    pub fn adaptive_concurrency(mut self, controller: Arc<AdaptiveConcurrency>) -> RunOptions {
+        self.adaptive = Some(controller);
+        self
+    }
+
+    /// Mirrors the requests of type `R` to the alternate implementation of
+    /// `config`, after they have been interposed on. Overridden requests are
+    /// not shadowed.
+    pub fn shadow<R, T, E>(mut self, config: ShadowConfig<R, T, E>) -> RunOptions
+    where
+        R: Request<T, E> + fmt::Debug + Send + 'static,
+        T: Clone + fmt::Debug + Send + 'static,
+        E: Send + 'static,
+    {
+        self.shadows.insert::<R>(config.into_shadow());
+        self
+    }
+}
+
+/// Why a run did not produce a value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RunError<E> {
+    /// The fetch threw.
+    Thrown(E),
+    /// The run was aborted by `RunOptions::deadline_in`.
+    DeadlineExceeded,
+    /// The run was aborted by `RunOptions::max_distinct_requests`.
+    TooManyRequests { limit: usize },
+    /// The run was aborted by `RunOptions::strict_overrides` before running
+    /// a request of the named type.
+    MissingOverride { request: &'static str },
+}
+
+/// Counters describing a finished run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RunStats {
+    /// Number of rounds of requests dispatched.
+    pub rounds: usize,
+    /// Number of requests dispatched over all rounds.
+    pub requests: usize,
+    /// Wall time of the whole run.
+    pub elapsed: Duration,
+    /// The limit of the `RunOptions::adaptive_concurrency` controller once
+    /// the run finished.
+    pub concurrency_limit: Option<usize>,
+    /// Present when the run was started with `collect_critical_path(true)`.
+    pub critical_path: Option<CriticalPath>,
+    /// The number of requests of each type, by type name, in every round.
+    /// Present when the run was started with `collect_round_breakdown(true)`.
+    pub round_breakdown: Option<Vec<HashMap<&'static str, usize>>>,
+}
+
+/// The per-round composition of a run, from `Fetch::run_with_detailed_stats`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DetailedStats {
+    /// The number of requests of each type, by type name, in every round.
+    pub rounds: Vec<HashMap<&'static str, usize>>,
+    /// The other counters of the run; its `round_breakdown` is moved to
+    /// `rounds`.
+    pub stats: RunStats,
+}
+
+pub struct RunOutcome<T, E> {
+    pub result: Result<T, RunError<E>>,
+    /// Present when the run was started with `collect_stats(true)`.
+    pub stats: Option<RunStats>,
+}
+
+impl<T: 'static, E: 'static> Fetch<T, E> {
+    pub fn run_with(self, options: RunOptions) -> RunOutcome<T, E> {
+        let start = Instant::now();
+        #[cfg(feature = "trace")]
+        let _trace = if options.trace_values {
+            Some(TraceGuard::enable(Some(
+                options.trace_value_len.unwrap_or(128),
+            )))
+        } else {
+            None
+        };
+        let longest = Arc::new(Mutex::new(CriticalPath::default()));
+        let critical = if options.collect_critical_path {
+            Some(CriticalGuard::enable(longest.clone()))
+        } else {
+            None
+        };
+        let mut stats = RunStats::default();
+        let mut breakdown = Vec::new();
+        let mut distinct = HashSet::new();
+        let mut deferred = Vec::new();
+        let mut last_start = HashMap::new();
+        let mut step = self.get()();
+        let result = loop {
+            match step {
+                ReqResult::Done(a) => break Ok(a),
+                ReqResult::Throw(e) => break Err(RunError::Thrown(e)),
+                ReqResult::Blocked(mut br, c) => {
+                    if !options.interceptors.0.is_empty() {
+                        for req in &mut br {
+                            if let Some((_, f)) = options.interceptors.0.get(&req.key.type_id) {
+                                if let Some(f) = f.downcast_ref::<Interceptor>() {
+                                    f(req.pending.request_mut());
+                                    req.rekey();
+                                }
+                            }
+                        }
+                    }
+                    if let Some(deadline) = options.deadline {
+                        if start.elapsed() >= deadline {
+                            break Err(RunError::DeadlineExceeded);
+                        }
+                    }
+                    if let Some(limit) = options.max_distinct_requests {
+                        distinct.extend(br.iter().map(|req| req.key));
+                        if distinct.len() > limit {
+                            break Err(RunError::TooManyRequests { limit });
+                        }
+                    }
+                    stats.requests += br.len();
+                    let mut round = vec_merge(mem::take(&mut deferred), br);
+                    if !round.is_empty() {
+                        stats.rounds += 1;
+                        if options.collect_round_breakdown {
+                            let mut counts = HashMap::new();
+                            for req in &round {
+                                *counts.entry(req.pending.type_name()).or_insert(0) += 1;
+                            }
+                            breakdown.push(counts);
+                        }
+                        if let Some(Exporter(exporter)) = &options.exporter {
+                            exporter.record_round(round.len());
+                            for req in &mut round {
+                                req.exporter = Some(exporter.clone());
+                            }
+                        }
+                    }
+                    if !options.sources.0.is_empty() {
+                        round.retain_mut(|req| match options.sources.0.get(&req.key.type_id) {
+                            Some((_, source)) if req.pending.answer(&**source) => {
+                                if let Some(path) = &req.path {
+                                    path.record(Duration::ZERO);
+                                }
+                                false
+                            }
+                            _ => true,
+                        });
+                    }
+                    if options.strict_overrides {
+                        let unanswered = round
+                            .iter()
+                            .find(|req| !options.compared.0.contains_key(&req.key.type_id));
+                        if let Some(req) = unanswered {
+                            break Err(RunError::MissingOverride {
+                                request: req.pending.type_name(),
+                            });
+                        }
+                    }
+                    if !options.compared.0.is_empty() {
+                        round = round
+                            .into_iter()
+                            .map(|mut req| {
+                                if let Some((_, source)) = options.compared.0.get(&req.key.type_id)
+                                {
+                                    req.pending = req.pending.sourced(&**source);
+                                }
+                                req
+                            })
+                            .collect();
+                    }
+                    if !options.shadows.0.is_empty() {
+                        round = round
+                            .into_iter()
+                            .map(|mut req| {
+                                if let Some((_, shadow)) = options.shadows.0.get(&req.key.type_id) {
+                                    req.pending = req.pending.shadowed(&**shadow);
+                                }
+                                req
+                            })
+                            .collect();
+                    }
+                    if let Some(spacing) = options.throttle_spacing {
+                        let now = Instant::now();
+                        for req in &mut round {
+                            if let Some(key) = req.throttle {
+                                let slot = match last_start.get(&key) {
+                                    Some(&last) if last + spacing > now => last + spacing,
+                                    _ => now,
+                                };
+                                last_start.insert(key, slot);
+                                req.not_before = Some(slot);
+                            }
+                        }
+                    }
+                    if !round.is_empty() {
+                        let run_round = || match (&options.adaptive, options.round_budget) {
+                            (Some(adaptive), budget) => adaptive.run_round(round, budget),
+                            (None, Some(budget)) => AbsRequest::run_within(round, budget),
+                            (None, None) => {
+                                AbsRequest::run_all(round);
+                                Vec::new()
+                            }
+                        };
+                        deferred = match &options.pool {
+                            Some(pool) => pool.install(run_round),
+                            None => run_round(),
+                        };
+                    }
+                    if let Some(critical) = &critical {
+                        critical.next_step();
+                    }
+                    step = c.get()();
+                }
+            }
+        };
+        stats.elapsed = start.elapsed();
+        if let Some(Exporter(exporter)) = &options.exporter {
+            exporter.record_duration(stats.elapsed);
+        }
+        stats.concurrency_limit = options.adaptive.as_ref().map(|adaptive| adaptive.limit());
+        drop(critical);
+        if options.collect_critical_path {
+            stats.critical_path = Some(*longest.lock().unwrap());
+        }
+        if options.collect_round_breakdown {
+            stats.round_breakdown = Some(breakdown);
+        }
+        RunOutcome {
+            result,
+            stats: if options.collect_stats
+                || options.collect_critical_path
+                || options.collect_round_breakdown
+            {
+                Some(stats)
+            } else {
+                None
+            },
+        }
+    }
+}
+
+impl<T: 'static, E: 'static> Fetch<T, E> {
+    /// Runs this fetch, streaming metrics about the run to `exporter`.
+    pub fn run_with_exporter(self, exporter: Arc<dyn MetricsExporter>) -> Result<T, E> {
+        match self
+            .run_with(RunOptions::new().metrics_exporter(exporter))
+            .result
+        {
+            Ok(a) => Ok(a),
+            Err(RunError::Thrown(e)) => Err(e),
+            Err(_) => unreachable!("default options never abort a run"),
+        }
+    }
+}
+
+impl<T: 'static, E: 'static> Fetch<T, E> {
+    /// Runs this fetch, reporting how many requests of each type every
+    /// round dispatched.
+    pub fn run_with_detailed_stats(self) -> (Result<T, E>, DetailedStats) {
+        let outcome = self.run_with(RunOptions::new().collect_round_breakdown(true));
+        let mut stats = outcome.stats.unwrap_or_default();
+        let rounds = stats.round_breakdown.take().unwrap_or_default();
+        let result = match outcome.result {
+            Ok(a) => Ok(a),
+            Err(RunError::Thrown(e)) => Err(e),
+            Err(_) => unreachable!("default options never abort a run"),
+        };
+        (result, DetailedStats { rounds, stats })
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl<T: 'static, E: 'static> Fetch<T, E> {
+    /// Runs this fetch, panicking if it issued any request, for tests
+    /// checking that a code path is purely computational. Available with
+    /// the `test-util` feature.
+    #[track_caller]
+    pub fn assert_no_requests(self) -> Result<T, E> {
+        let outcome = self.run_with(RunOptions::new().collect_stats(true));
+        let requests = outcome.stats.map_or(0, |stats| stats.requests);
+        assert!(
+            requests == 0,
+            "expected a fetch without requests, but it issued {}",
+            requests
+        );
+        match outcome.result {
+            Ok(a) => Ok(a),
+            Err(RunError::Thrown(e)) => Err(e),
+            Err(_) => unreachable!("default options never abort a run"),
+        }
+    }
+}
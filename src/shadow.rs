@@ -0,0 +1,156 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::{Hook, Request, Shadow, Source};
+
+type Compare<T> = Box<dyn Fn(&T, &T) -> bool + Send + Sync>;
+
+/// Mirrors requests of one type to an alternate implementation, for
+/// instance a data source being migrated to, and compares the results.
+/// Attached to a run with `RunOptions::shadow`.
+///
+/// The returned value is always the primary one. Once a sampled request
+/// finishes, its shadow call is queued on the rayon pool that ran it, so
+/// the round never waits for it and shadows never add threads of their
+/// own; errors of the shadow call are only counted.
+pub struct ShadowConfig<R, T, E> {
+    sample: Box<dyn Fn(&R) -> bool + Send + Sync>,
+    alternate: Source<R, T, E>,
+    compare: Compare<T>,
+    on_mismatch: Box<dyn Fn(&ShadowMismatch) + Send + Sync>,
+    counters: Arc<ShadowCounters>,
+}
+
+/// A shadow call that disagreed with the primary.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShadowMismatch {
+    /// The `Debug` rendering of the request.
+    pub request: String,
+    pub primary: String,
+    pub shadow: String,
+}
+
+/// Outcomes of the shadow calls made for one `ShadowConfig`, updated as
+/// they finish.
+#[derive(Debug, Default)]
+pub struct ShadowCounters {
+    finished: AtomicUsize,
+    compared: AtomicUsize,
+    mismatched: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+impl ShadowCounters {
+    /// Shadow calls that have finished, whatever their outcome.
+    pub fn finished(&self) -> usize {
+        self.finished.load(Ordering::SeqCst)
+    }
+
+    /// Shadow calls whose result was compared with the primary's. Calls
+    /// are only compared when both sides succeed.
+    pub fn compared(&self) -> usize {
+        self.compared.load(Ordering::SeqCst)
+    }
+
+    pub fn mismatched(&self) -> usize {
+        self.mismatched.load(Ordering::SeqCst)
+    }
+
+    /// Shadow calls that returned an error.
+    pub fn failed(&self) -> usize {
+        self.failed.load(Ordering::SeqCst)
+    }
+}
+
+impl<R, T: PartialEq, E> ShadowConfig<R, T, E> {
+    /// Shadows every request with `alternate`, comparing the results with
+    /// `PartialEq` and ignoring mismatches beyond counting them.
+    pub fn new(alternate: impl Fn(&R) -> Result<T, E> + Send + Sync + 'static) -> Self {
+        ShadowConfig {
+            sample: Box::new(|_| true),
+            alternate: Box::new(alternate),
+            compare: Box::new(|a: &T, b: &T| a == b),
+            on_mismatch: Box::new(|_| {}),
+            counters: Arc::default(),
+        }
+    }
+}
+
+impl<R, T, E> ShadowConfig<R, T, E> {
+    /// Shadows only the requests for which `sample` returns true.
+    pub fn sample(mut self, sample: impl Fn(&R) -> bool + Send + Sync + 'static) -> Self {
+        self.sample = Box::new(sample);
+        self
+    }
+
+    /// Shadows a random `rate` of the requests.
+    pub fn sample_rate(self, rate: f64) -> Self {
+        self.sample(move |_| rand::random::<f64>() < rate)
+    }
+
+    /// Compares results with `compare` instead of `PartialEq`.
+    pub fn compare_with(
+        mut self,
+        compare: impl Fn(&T, &T) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.compare = Box::new(compare);
+        self
+    }
+
+    /// Calls `f` for every mismatch, on the thread of the shadow call.
+    pub fn on_mismatch(mut self, f: impl Fn(&ShadowMismatch) + Send + Sync + 'static) -> Self {
+        self.on_mismatch = Box::new(f);
+        self
+    }
+
+    pub fn counters(&self) -> Arc<ShadowCounters> {
+        self.counters.clone()
+    }
+}
+
+impl<R, T, E> ShadowConfig<R, T, E>
+where
+    R: Request<T, E> + fmt::Debug + Send + 'static,
+    T: Clone + fmt::Debug + Send + 'static,
+    E: Send + 'static,
+{
+    pub(crate) fn into_shadow(self) -> Shadow<R, T, E> {
+        let config = Arc::new(self);
+        Box::new(move |request: &R| {
+            if !(config.sample)(request) {
+                return None;
+            }
+            let (config, request) = (config.clone(), request.clone());
+            let hook: Hook<T, E> = Box::new(move |res: &Result<T, E>| {
+                let primary = res.as_ref().ok().cloned();
+                rayon::spawn(move || config.run_shadow(&request, primary));
+            });
+            Some(hook)
+        })
+    }
+
+    fn run_shadow(&self, request: &R, primary: Option<T>) {
+        let shadow = (self.alternate)(request);
+        let counters = &self.counters;
+        match (shadow, primary) {
+            (Err(_), _) => {
+                counters.failed.fetch_add(1, Ordering::SeqCst);
+            }
+            // the primary threw, so there is nothing to compare with
+            (Ok(_), None) => {}
+            (Ok(shadow), Some(primary)) => {
+                counters.compared.fetch_add(1, Ordering::SeqCst);
+                if !(self.compare)(&primary, &shadow) {
+                    counters.mismatched.fetch_add(1, Ordering::SeqCst);
+                    (self.on_mismatch)(&ShadowMismatch {
+                        request: format!("{:?}", request),
+                        primary: format!("{:?}", primary),
+                        shadow: format!("{:?}", shadow),
+                    });
+                }
+            }
+        }
+        counters.finished.fetch_add(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,107 @@
+#[cfg(feature = "trace")]
+use std::cell::Cell;
+use std::fmt;
+#[cfg(feature = "trace")]
+use std::panic::Location;
+use std::sync::mpsc::Sender;
+
+use crate::{Fetch, ReqResult};
+
+#[cfg(feature = "trace")]
+thread_local! {
+    // Maximum length of a traced value, while a run with
+    // `RunOptions::trace_values` is stepping on this thread.
+    static TRACE_LEN: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+// Enables tracing on the current thread until the guard is dropped.
+#[cfg(feature = "trace")]
+pub(crate) struct TraceGuard(Option<usize>);
+
+#[cfg(feature = "trace")]
+impl TraceGuard {
+    pub(crate) fn enable(max_len: Option<usize>) -> TraceGuard {
+        TraceGuard(TRACE_LEN.with(|len| len.replace(max_len)))
+    }
+}
+
+#[cfg(feature = "trace")]
+impl Drop for TraceGuard {
+    fn drop(&mut self) {
+        TRACE_LEN.with(|len| len.set(self.0));
+    }
+}
+
+#[cfg(feature = "trace")]
+fn truncate(mut rendered: String, max_len: usize) -> String {
+    if rendered.len() > max_len {
+        let mut end = max_len;
+        while !rendered.is_char_boundary(end) {
+            end -= 1;
+        }
+        rendered.truncate(end);
+        rendered.push_str("...");
+    }
+    rendered
+}
+
+impl<T: fmt::Debug + 'static, E: 'static> Fetch<T, E> {
+    /// Marks a point of the pipeline whose value is logged, at trace level
+    /// with target `ruxl::trace`, when it flows through a run started with
+    /// `RunOptions::trace_values`. The log record carries `label`, the
+    /// caller's location and the value's `Debug` rendering, truncated to the
+    /// run's configured length.
+    ///
+    /// Only values passing through `traced` points are logged: `bind`,
+    /// `fmap` and `ap` are not traced on their own, since their values need
+    /// not implement `Debug`. Logging needs the `trace` feature; without it,
+    /// and outside of tracing runs, a traced fetch behaves exactly like the
+    /// original, so call sites can stay in place.
+    #[track_caller]
+    pub fn traced(self, label: &'static str) -> Fetch<T, E> {
+        #[cfg(feature = "trace")]
+        let traced = self.traced_at(label, Location::caller());
+        #[cfg(not(feature = "trace"))]
+        let traced = {
+            let _ = label;
+            self
+        };
+        traced
+    }
+
+    /// Sends the `Debug` rendering of this fetch's value over `tx` once it
+    /// is available, passing the value on unchanged.
+    ///
+    /// Only the rendering crosses threads, so `T` need not be `Send`. A
+    /// disconnected receiver is ignored.
+    pub fn observe_value(self, tx: Sender<String>) -> Fetch<T, E> {
+        Fetch(Box::new(move || match self.get()() {
+            ReqResult::Done(a) => {
+                let _ = tx.send(format!("{:?}", a));
+                ReqResult::Done(a)
+            }
+            ReqResult::Blocked(br, c) => ReqResult::Blocked(br, c.observe_value(tx)),
+            ReqResult::Throw(e) => ReqResult::Throw(e),
+        }))
+    }
+
+    #[cfg(feature = "trace")]
+    fn traced_at(self, label: &'static str, location: &'static Location<'static>) -> Fetch<T, E> {
+        Fetch(Box::new(move || match self.get()() {
+            ReqResult::Done(a) => {
+                if let Some(max_len) = TRACE_LEN.with(Cell::get) {
+                    log::trace!(
+                        target: "ruxl::trace",
+                        "{} at {}: {}",
+                        label,
+                        location,
+                        truncate(format!("{:?}", a), max_len)
+                    );
+                }
+                ReqResult::Done(a)
+            }
+            ReqResult::Blocked(br, c) => ReqResult::Blocked(br, c.traced_at(label, location)),
+            ReqResult::Throw(e) => ReqResult::Throw(e),
+        }))
+    }
+}
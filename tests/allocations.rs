@@ -0,0 +1,46 @@
+// Counts reallocations to check that `traverse_sized` collects its results
+// without growing the vector. This is its own test binary, so no other test
+// allocates while a count is taken.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ruxl::{Fetch, Impossible, Traversable};
+
+struct CountingAlloc;
+
+static REALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        REALLOCS.fetch_add(1, Ordering::SeqCst);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+fn reallocs_of(traversal: impl FnOnce() -> Fetch<Vec<usize>, Impossible>) -> usize {
+    let before = REALLOCS.load(Ordering::SeqCst);
+    let results = traversal().run().unwrap_or_else(|e| match e {});
+    let reallocs = REALLOCS.load(Ordering::SeqCst) - before;
+    assert_eq!(results, (0..100).collect::<Vec<_>>());
+    reallocs
+}
+
+#[test]
+fn traverse_sized_does_not_grow_the_results() {
+    let sized = reallocs_of(|| (0..100).traverse_sized(100, Fetch::pure));
+    let plain = reallocs_of(|| (0..100).traverse(Fetch::pure));
+    assert_eq!(sized, 0);
+    assert!(plain > 0, "the plain traversal never grew its results");
+}
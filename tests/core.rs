@@ -0,0 +1,103 @@
+// `ap` rebuilt on the public stepping API, checked against the batching
+// behavior of the built-in one.
+
+use ruxl::core::{BlockedRequests, Step};
+use ruxl::{ap, throw, Fetch, Request, RunOptions};
+
+fn my_ap<T, U, F, E>(f: Fetch<F, E>, x: Fetch<T, E>) -> Fetch<U, E>
+where
+    T: 'static,
+    U: 'static,
+    E: 'static,
+    F: FnOnce(T) -> U + 'static,
+{
+    Fetch::from_step_fn(|| match (f.force_step(), x.force_step()) {
+        (Step::Throw(e), _) => Step::Throw(e),
+        (Step::Done(f), Step::Done(x)) => Step::Done(f(x)),
+        (Step::Done(_), Step::Throw(e)) => Step::Throw(e),
+        (Step::Done(f), Step::Blocked(br, c)) => Step::Blocked(br, c.fmap(f)),
+        (Step::Blocked(br, c), Step::Done(x)) => Step::Blocked(br, my_ap(c, Fetch::pure(x))),
+        (Step::Blocked(br, c), Step::Throw(e)) => Step::Blocked(br, my_ap(c, throw(e))),
+        (Step::Blocked(br1, f), Step::Blocked(br2, x)) => {
+            Step::Blocked(br1.merge(br2), my_ap(f, x))
+        }
+    })
+}
+
+#[derive(Clone, Hash, PartialEq, Eq)]
+struct Get(usize);
+
+impl Request<usize, String> for Get {
+    fn run(self) -> Result<usize, String> {
+        if self.0 == 0 {
+            Err("zero".to_string())
+        } else {
+            Ok(self.0)
+        }
+    }
+}
+
+fn get(n: usize) -> Fetch<usize, String> {
+    Fetch::new(Get(n))
+}
+
+// `n` chained requests, issued one round after another
+fn chain(n: usize) -> Fetch<usize, String> {
+    (1..n).fold(get(1), |acc, _| acc.bind(|m| get(m + 1)))
+}
+
+type Ap = fn(
+    Fetch<Box<dyn FnOnce(usize) -> usize>, String>,
+    Fetch<usize, String>,
+) -> Fetch<usize, String>;
+
+fn add(apply: Ap, a: Fetch<usize, String>, b: Fetch<usize, String>) -> Fetch<usize, String> {
+    let f = a.fmap(|a| Box::new(move |b| a + b) as Box<dyn FnOnce(usize) -> usize>);
+    apply(f, b)
+}
+
+fn rounds_and_result(fetch: Fetch<usize, String>) -> (usize, Result<usize, String>) {
+    let outcome = fetch.run_with(RunOptions::new().collect_stats(true));
+    let rounds = outcome.stats.unwrap().rounds;
+    let result = outcome.result.map_err(|e| format!("{:?}", e));
+    (rounds, result)
+}
+
+#[test]
+fn rebuilt_ap_batches_like_ap() {
+    for apply in [ap as Ap, my_ap as Ap] {
+        assert_eq!(rounds_and_result(add(apply, get(1), get(2))), (1, Ok(3)));
+        // the shorter side finishes early and waits for the longer one
+        assert_eq!(rounds_and_result(add(apply, chain(3), get(2))), (3, Ok(5)));
+        assert_eq!(rounds_and_result(add(apply, get(2), chain(2))), (2, Ok(4)));
+    }
+}
+
+#[test]
+fn rebuilt_ap_throws_like_ap() {
+    for apply in [ap as Ap, my_ap as Ap] {
+        let (rounds, result) = rounds_and_result(add(apply, chain(2), get(0)));
+        assert_eq!(rounds, 2);
+        assert_eq!(result, Err("Thrown(\"zero\")".to_string()));
+    }
+}
+
+#[test]
+fn blocked_requests_merge() {
+    let (a, b) = match (get(1).force_step(), chain(2).force_step()) {
+        (Step::Blocked(a, _), Step::Blocked(b, _)) => (a, b),
+        _ => panic!("leaves are blocked on their request"),
+    };
+    assert_eq!(a.merge(b).len(), 2);
+    assert!(BlockedRequests::new().is_empty());
+}
+
+#[test]
+#[should_panic(expected = "a request was dropped before it ran")]
+fn dropped_requests_are_reported() {
+    let dropping = Fetch::from_step_fn(|| match get(1).force_step() {
+        Step::Blocked(_, c) => Step::Blocked(BlockedRequests::new(), c),
+        _ => panic!("leaves are blocked on their request"),
+    });
+    let _ = dropping.run();
+}